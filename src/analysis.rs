@@ -0,0 +1,747 @@
+//! Offline helpers for generating and studying address traces
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+    thread,
+};
+
+use crate::{
+    patterns::SplitMix64,
+    thrashe::{CacheSpec, CacheState, ThrasheReport},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Traversal {
+    RowMajor,
+    ColumnMajor,
+}
+
+/// address stream for visiting every element of a row-major laid out `rows` x `cols` matrix
+pub fn matrix_trace(
+    base: u64,
+    rows: usize,
+    cols: usize,
+    elem_size: usize,
+    order: Traversal,
+) -> Vec<u64> {
+    let address = |r: usize, c: usize| base + ((r * cols + c) * elem_size) as u64;
+    let mut trace = Vec::with_capacity(rows * cols);
+    match order {
+        Traversal::RowMajor => {
+            for r in 0..rows {
+                for c in 0..cols {
+                    trace.push(address(r, c));
+                }
+            }
+        }
+        Traversal::ColumnMajor => {
+            for c in 0..cols {
+                for r in 0..rows {
+                    trace.push(address(r, c));
+                }
+            }
+        }
+    }
+    trace
+}
+
+/// `count` distinct blocks starting at `base` that all map to the same set of `spec`
+pub fn conflict_set(spec: &CacheSpec, base: u64, count: usize) -> Vec<u64> {
+    let stride = spec.conflict_stride();
+    (0..count as u64).map(|i| base + i * stride).collect()
+}
+
+/// Two blocks that kept evicting each other from the same set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictPair {
+    /// block aligned addresses, lowest first
+    pub blocks: (u64, u64),
+    pub set: usize,
+    /// times either evicted the other
+    pub evictions: u32,
+}
+
+/// replays `trace` through a cache of `spec` and returns the `top` pairs of blocks that evicted
+/// each other most, most evictions first
+///
+/// every eviction is blamed on the block filled in its place, so pairs in a set that's simply
+/// too small for its working set show up too
+pub fn conflict_culprits(spec: &CacheSpec, trace: &[u64], top: usize) -> Vec<ConflictPair> {
+    let cache = CacheState::from_spec(spec.clone());
+    let block_mask = !(spec.block_size() as u64 - 1);
+    let mut pairs: HashMap<(u64, u64), u32> = HashMap::new();
+    for &address in trace {
+        if let Some(evicted) = cache.access(address, false, true).evicted {
+            let block = address & block_mask;
+            *pairs
+                .entry((block.min(evicted), block.max(evicted)))
+                .or_default() += 1;
+        }
+    }
+
+    let mut culprits: Vec<ConflictPair> = pairs
+        .into_iter()
+        .map(|(blocks, evictions)| ConflictPair {
+            blocks,
+            set: spec.index_of(blocks.0),
+            evictions,
+        })
+        .collect();
+    culprits.sort_unstable_by(|a, b| b.evictions.cmp(&a.evictions).then(a.blocks.cmp(&b.blocks)));
+    culprits.truncate(top);
+    culprits
+}
+
+/// distinct blocks in `trace`, the misses no cache with this block size can avoid
+pub fn compulsory_floor(trace: &[u64], block_size_bits: u8) -> u64 {
+    let blocks: HashSet<u64> = trace.iter().map(|a| a >> block_size_bits).collect();
+    blocks.len() as u64
+}
+
+/// LRU stack distance of every access: how many distinct other blocks were touched since the
+/// previous access to the same block, `None` for the first touch of a block
+///
+/// a fully associative LRU cache of `n` lines hits exactly the accesses with a distance below `n`
+pub fn reuse_distances(trace: &[u64], block_size_bits: u8) -> Vec<Option<usize>> {
+    // marks the latest access of each block, so a range sum counts distinct blocks
+    let mut marks = Fenwick::new(trace.len());
+    let mut last_seen = HashMap::new();
+    trace
+        .iter()
+        .enumerate()
+        .map(|(time, &address)| {
+            let block = address >> block_size_bits;
+            let distance = last_seen.insert(block, time).map(|last| {
+                let distinct = marks.prefix(time) - marks.prefix(last);
+                marks.add(last, -1);
+                distinct as usize
+            });
+            marks.add(time, 1);
+            distance
+        })
+        .collect()
+}
+
+/// Bounds on the memory the opt-in trackers of a cache may use, see
+/// [`CacheState::with_analysis_budget`]
+///
+/// once a tracker holds `max_tracked_blocks` blocks new blocks are left out of it, so its results
+/// become approximate and [`ThrasheReport::approximate`] says so
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnalysisBudget {
+    pub max_tracked_blocks: usize,
+}
+
+/// Online version of [`reuse_distances`], fed one access at a time by a cache
+pub(crate) struct ReuseTracker {
+    state: Mutex<ReuseState>,
+}
+
+struct ReuseState {
+    marks: Fenwick,
+    last_seen: HashMap<u64, usize>,
+    time: usize,
+    hits: u64,
+    hit_distance_total: u64,
+    sketch: ReuseDistanceSketch,
+}
+
+impl ReuseState {
+    /// makes room for more accesses once the marks are full
+    ///
+    /// only the latest access of each block is marked and distances only depend on their order,
+    /// so the accesses are renumbered `0..blocks` and the marks never need more than twice as
+    /// many entries as there are blocks
+    fn rebuild_marks(&mut self) {
+        let mut latest: Vec<(usize, u64)> = self.last_seen.iter().map(|(&b, &t)| (t, b)).collect();
+        latest.sort_unstable();
+        let mut marks = Fenwick::new((2 * latest.len()).max(1024));
+        for (time, (_, block)) in latest.into_iter().enumerate() {
+            marks.add(time, 1);
+            self.last_seen.insert(block, time);
+        }
+        self.time = self.last_seen.len();
+        self.marks = marks;
+    }
+}
+
+impl ReuseTracker {
+    pub fn new() -> ReuseTracker {
+        ReuseTracker {
+            state: Mutex::new(ReuseState {
+                marks: Fenwick::new(1024),
+                last_seen: HashMap::new(),
+                time: 0,
+                hits: 0,
+                hit_distance_total: 0,
+                sketch: ReuseDistanceSketch::new(ReuseDistanceSketch::DEFAULT_CAPACITY),
+            }),
+        }
+    }
+
+    /// records an access to `block`, `hit` is whether the cache hit
+    ///
+    /// a block not seen before is left out if `max_blocks` are already tracked, returns whether
+    /// it was
+    pub fn record(&self, block: u64, hit: bool, max_blocks: usize) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.last_seen.len() >= max_blocks && !state.last_seen.contains_key(&block) {
+            return true;
+        }
+        if state.time == state.marks.len() {
+            state.rebuild_marks();
+        }
+        let time = state.time;
+        state.time += 1;
+
+        let distance = state.last_seen.insert(block, time).map(|last| {
+            let distinct = state.marks.prefix(time) - state.marks.prefix(last);
+            state.marks.add(last, -1);
+            distinct as u64
+        });
+        state.marks.add(time, 1);
+        if let Some(distance) = distance {
+            state.sketch.record(distance);
+        }
+        if let (true, Some(distance)) = (hit, distance) {
+            state.hits += 1;
+            state.hit_distance_total += distance;
+        }
+        false
+    }
+
+    pub fn sketch(&self) -> ReuseDistanceSketch {
+        self.state.lock().unwrap().sketch.clone()
+    }
+
+    /// `None` until there has been a hit
+    pub fn mean_hit_distance(&self) -> Option<f64> {
+        let state = self.state.lock().unwrap();
+        (state.hits > 0).then(|| state.hit_distance_total as f64 / state.hits as f64)
+    }
+}
+
+/// Approximate distribution of a stream of reuse distances in bounded memory, a uniform random
+/// sample of at most `capacity` of them
+///
+/// a percentile is exact for the sample, against the whole stream the rank of the value returned
+/// for `p` has a standard error of `sqrt(p * (1 - p) / capacity)`, e.g. 1.6% of the stream for
+/// the median with the default 1024 samples. Streams no longer than `capacity` are exact
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReuseDistanceSketch {
+    samples: Vec<u64>,
+    capacity: usize,
+    seen: u64,
+    rng: SplitMix64,
+}
+
+impl ReuseDistanceSketch {
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    pub fn new(capacity: usize) -> ReuseDistanceSketch {
+        assert!(capacity > 0, "a sketch needs room for at least one sample");
+        ReuseDistanceSketch {
+            samples: Vec::with_capacity(capacity),
+            capacity,
+            seen: 0,
+            rng: SplitMix64(capacity as u64),
+        }
+    }
+
+    pub fn record(&mut self, distance: u64) {
+        self.seen += 1;
+        if self.samples.len() < self.capacity {
+            self.samples.push(distance);
+        } else {
+            // reservoir sampling, the new distance replaces a sample with probability capacity / seen
+            let slot = self.rng.next() % self.seen;
+            if let Some(sample) = self.samples.get_mut(slot as usize) {
+                *sample = distance;
+            }
+        }
+    }
+
+    /// distances recorded, not just the ones sampled
+    pub fn count(&self) -> u64 {
+        self.seen
+    }
+
+    /// estimated smallest distance at least a fraction `p` of the stream is below or equal to,
+    /// `None` if nothing was recorded
+    pub fn percentile(&self, p: f64) -> Option<u64> {
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        let rank = (p.clamp(0.0, 1.0) * sorted.len() as f64).ceil() as usize;
+        sorted.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+struct Fenwick(Vec<i64>);
+
+impl Fenwick {
+    fn new(len: usize) -> Fenwick {
+        Fenwick(vec![0; len + 1])
+    }
+
+    fn len(&self) -> usize {
+        self.0.len() - 1
+    }
+
+    fn add(&mut self, index: usize, delta: i64) {
+        let mut i = index + 1;
+        while i < self.0.len() {
+            self.0[i] += delta;
+            i += i & i.wrapping_neg();
+        }
+    }
+
+    /// sum of `0..=index`
+    fn prefix(&self, index: usize) -> i64 {
+        let mut i = index + 1;
+        let mut sum = 0;
+        while i > 0 {
+            sum += self.0[i];
+            i -= i & i.wrapping_neg();
+        }
+        sum
+    }
+}
+
+/// smallest cache with the given block size and associativity predicted to reach `target` hit rate
+///
+/// the prediction treats the cache as fully associative LRU, so conflict misses are ignored. If
+/// `target` can't be reached the smallest cache that holds every block of the trace is returned
+pub fn size_for_hit_rate(
+    trace: &[u64],
+    block_size_bits: u8,
+    assoc_bits: u8,
+    target: f64,
+) -> CacheSpec {
+    let distances = reuse_distances(trace, block_size_bits);
+    let mut sorted: Vec<usize> = distances.iter().flatten().copied().collect();
+    sorted.sort_unstable();
+    let blocks = distances.len() - sorted.len();
+
+    let predicted_hit_rate = |set_num_bits: u8| {
+        let lines = 1usize << (set_num_bits + assoc_bits);
+        let hits = sorted.partition_point(|&d| d < lines);
+        if trace.is_empty() {
+            0.0
+        } else {
+            hits as f64 / trace.len() as f64
+        }
+    };
+
+    let mut high = 0;
+    while (1usize << (high + assoc_bits)) < blocks {
+        high += 1;
+    }
+    let mut low = 0;
+    while low < high {
+        let mid = (low + high) / 2;
+        if predicted_hit_rate(mid) >= target {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    CacheSpec::new(block_size_bits, low, assoc_bits)
+}
+
+/// replays `trace` twice through one cache, returning the report of the first pass from a cold
+/// cache and of just the second pass, which starts with whatever the first left behind
+///
+/// what the warm pass still misses is the steady state, the difference is warm up
+pub fn cold_vs_warm(spec: &CacheSpec, trace: &[u64]) -> (ThrasheReport, ThrasheReport) {
+    let cache = CacheState::from_spec(spec.clone());
+    cache.replay(trace);
+    let cold = cache.make_report();
+    cache.reset_stats();
+    cache.replay(trace);
+    (cold, cache.make_report())
+}
+
+/// seed [`replay_orderings`] shuffles with, so its results are reproducible
+pub const SHUFFLE_SEED: u64 = 0x5eed;
+
+/// The same trace replayed in different orders, see [`replay_orderings`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingReport {
+    pub forward: ThrasheReport,
+    pub reversed: ThrasheReport,
+    /// the runs of accesses to one block shuffled with [`SHUFFLE_SEED`], each run kept whole
+    pub shuffled: ThrasheReport,
+}
+
+impl OrderingReport {
+    /// the difference between the best and worst hit rate of the orderings, near 0 when the
+    /// result hardly depends on the order of the accesses
+    pub fn hit_rate_spread(&self) -> f64 {
+        let rates = [&self.forward, &self.reversed, &self.shuffled].map(|r| r.hit_rate());
+        let max = rates.iter().copied().fold(f64::MIN, f64::max);
+        let min = rates.iter().copied().fold(f64::MAX, f64::min);
+        max - min
+    }
+}
+
+/// replays `trace` forwards, backwards, and shuffled, each through a cold cache of `spec`
+///
+/// the shuffle moves whole runs of consecutive accesses to the same block, so the hits of walking
+/// through a block survive it and only the reuse between runs is scattered
+///
+/// a large [`OrderingReport::hit_rate_spread`] means the result relies on the order of the trace,
+/// e.g. on reuse following soon after an access, rather than just on which blocks it touches
+pub fn replay_orderings(spec: &CacheSpec, trace: &[u64]) -> OrderingReport {
+    let replay = |trace: &[u64]| {
+        let cache = CacheState::from_spec(spec.clone());
+        cache.replay(trace);
+        cache.make_report()
+    };
+
+    let reversed: Vec<u64> = trace.iter().rev().copied().collect();
+    let shuffled = shuffle_block_runs(trace, spec.block_size_bits(), SHUFFLE_SEED);
+    OrderingReport {
+        forward: replay(trace),
+        reversed: replay(&reversed),
+        shuffled: replay(&shuffled),
+    }
+}
+
+/// `trace` with its runs of consecutive accesses to the same block in a random order, the
+/// accesses within a run keep theirs
+fn shuffle_block_runs(trace: &[u64], block_size_bits: u8, seed: u64) -> Vec<u64> {
+    let mut runs: Vec<&[u64]> = trace
+        .chunk_by(|a, b| a >> block_size_bits == b >> block_size_bits)
+        .collect();
+    let mut rng = SplitMix64(seed);
+    for i in (1..runs.len()).rev() {
+        let j = (rng.next() % (i as u64 + 1)) as usize;
+        runs.swap(i, j);
+    }
+    runs.concat()
+}
+
+/// replays `trace` once for every block size from `2^min_block_bits` to `2^max_block_bits` bytes
+///
+/// the number of sets and ways stays fixed, so larger blocks also mean a larger cache
+pub fn block_size_sweep(
+    trace: &[u64],
+    set_num_bits: u8,
+    assoc_bits: u8,
+    min_block_bits: u8,
+    max_block_bits: u8,
+) -> Vec<(u8, ThrasheReport)> {
+    (min_block_bits..=max_block_bits)
+        .map(|block_bits| {
+            let cache = CacheState::from_spec(CacheSpec::new(block_bits, set_num_bits, assoc_bits));
+            cache.replay(trace);
+            (block_bits, cache.make_report())
+        })
+        .collect()
+}
+
+/// replays `trace` against every spec, each on its own thread, returning the reports in the order of
+/// `specs`
+///
+/// each thread owns its cache outright, so no lock or provider is involved
+pub fn parallel_sweep(specs: &[CacheSpec], trace: &[u64]) -> Vec<ThrasheReport> {
+    thread::scope(|s| {
+        let handles: Vec<_> = specs
+            .iter()
+            .map(|spec| {
+                s.spawn(move || {
+                    let cache = CacheState::from_spec(spec.clone());
+                    cache.replay(trace);
+                    cache.make_report()
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::patterns;
+
+    #[test]
+    fn order_sensitivity() {
+        let spec = CacheSpec::spec_8kib_32bit_2way();
+        // 16 groups of half the cache, each walked 8 times reading every word of a block
+        let reuse: Vec<u64> = (0..16u64)
+            .flat_map(|group| {
+                let blocks = (0..128).map(move |block| (group * 128 + block) * 32);
+                std::iter::repeat_n(blocks, 8).flatten()
+            })
+            .flat_map(|block| (0..4).map(move |word| block + 8 * word))
+            .collect();
+        let runs = 16 * 8 * 128;
+        let orderings = replay_orderings(&spec, &reuse);
+        assert_eq!(orderings.forward.misses(), 16 * 128);
+        assert_eq!(orderings.reversed.misses(), 16 * 128);
+        // the walk of each block stays together, but the groups are broken up
+        assert!(orderings.shuffled.hits() >= 3 * runs);
+        assert!(orderings.shuffled.misses() > runs / 2);
+        assert!(orderings.hit_rate_spread() > 0.1);
+
+        let random: Vec<u64> = patterns::random_uniform(0, 1 << 16, 8192, 9).collect();
+        let orderings = replay_orderings(&spec, &random);
+        assert!(orderings.hit_rate_spread() < 0.05);
+    }
+
+    #[test]
+    fn shuffled_runs_keep_their_order() {
+        // a run of a different length for every block, each block in just one run
+        let trace: Vec<u64> = (0..64u64)
+            .flat_map(|block| (0..=block % 4).map(move |word| block * 32 + 8 * word))
+            .collect();
+        let shuffled = shuffle_block_runs(&trace, 5, SHUFFLE_SEED);
+        assert_ne!(shuffled, trace);
+        let mut sorted = shuffled.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, trace);
+        for block in 0..64 {
+            let accesses = |trace: &[u64]| -> Vec<u64> {
+                trace.iter().copied().filter(|a| a >> 5 == block).collect()
+            };
+            assert_eq!(accesses(&shuffled), accesses(&trace));
+        }
+        // and each one's accesses are still next to each other
+        assert_eq!(shuffled.chunk_by(|a, b| a >> 5 == b >> 5).count(), 64);
+    }
+
+    #[test]
+    fn loop_order() {
+        let (rows, cols) = (64, 512);
+        let row_major = matrix_trace(4096, rows, cols, 8, Traversal::RowMajor);
+        let col_major = matrix_trace(4096, rows, cols, 8, Traversal::ColumnMajor);
+        assert_eq!(row_major.len(), rows * cols);
+        assert_eq!(col_major.len(), rows * cols);
+
+        let row_cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        row_cache.replay(&row_major);
+        let col_cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        col_cache.replay(&col_major);
+
+        let row_report = row_cache.make_report();
+        let col_report = col_cache.make_report();
+        assert_eq!(row_report.misses(), (rows * cols / 4) as u64);
+        assert!(col_report.misses() > 3 * row_report.misses());
+    }
+
+    #[test]
+    fn conflicting_addresses() {
+        let spec = CacheSpec::spec_8kib_32bit_2way();
+        let addresses = conflict_set(&spec, 4200, 3);
+        assert_eq!(addresses.len(), 3);
+        assert!(
+            addresses
+                .iter()
+                .all(|&a| spec.index_of(a) == spec.index_of(4200))
+        );
+
+        // one more block than there are ways never hits
+        let cache = CacheState::from_spec(spec);
+        for _ in 0..10 {
+            cache.replay(&addresses);
+        }
+        assert_eq!(cache.make_report().hits(), 0);
+    }
+
+    #[test]
+    fn conflicting_arrays() {
+        // two arrays exactly one direct mapped cache apart, walked together
+        let spec = CacheSpec::new(5, 7, 0);
+        let (a, b) = (0, spec.conflict_stride());
+        let mut trace = Vec::new();
+        for _ in 0..10 {
+            for i in 0..4 {
+                trace.extend([a + 32 * i, b + 32 * i]);
+            }
+        }
+        // and a single sweep that evicts each of them once more
+        trace.extend((0..64).map(|i| 8192 + 32 * i));
+
+        let culprits = conflict_culprits(&spec, &trace, 4);
+        assert_eq!(culprits.len(), 4);
+        for (i, culprit) in culprits.iter().enumerate() {
+            let i = i as u64;
+            assert_eq!(culprit.blocks, (a + 32 * i, b + 32 * i));
+            assert_eq!(culprit.set, i as usize);
+            assert_eq!(culprit.evictions, 19);
+        }
+    }
+
+    #[test]
+    fn distinct_blocks() {
+        let trace = [0, 8, 31, 32, 4096, 0, 4100];
+        assert_eq!(compulsory_floor(&trace, 5), 3);
+        assert_eq!(compulsory_floor(&trace, 12), 2);
+        assert_eq!(compulsory_floor(&[], 5), 0);
+
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        cache.replay(&trace);
+        assert_eq!(cache.make_report().misses(), compulsory_floor(&trace, 5));
+    }
+
+    #[test]
+    fn stack_distances() {
+        let trace = [0, 32, 64, 0, 0, 64, 96];
+        assert_eq!(
+            reuse_distances(&trace, 5),
+            [None, None, None, Some(2), Some(0), Some(1), None]
+        );
+    }
+
+    #[test]
+    fn sketched_percentiles() {
+        let trace: Vec<u64> = patterns::random_uniform(0, 2048 * 32, 50_000, 9).collect();
+        let mut exact: Vec<u64> = reuse_distances(&trace, 5)
+            .into_iter()
+            .flatten()
+            .map(|d| d as u64)
+            .collect();
+        exact.sort_unstable();
+        let exact_percentile = |p: f64| exact[(p * exact.len() as f64).ceil() as usize - 1];
+
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).with_reuse_distances();
+        cache.replay(&trace);
+        let report = cache.make_report();
+        for p in [0.5, 0.9] {
+            let estimate = report.reuse_distance_percentile(p).unwrap() as f64;
+            let exact = exact_percentile(p) as f64;
+            assert!(
+                (estimate - exact).abs() / exact < 0.1,
+                "p{p}: {estimate} vs {exact}"
+            );
+        }
+        assert!(
+            CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way())
+                .make_report()
+                .reuse_distance_percentile(0.5)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn bounded_trackers() {
+        let budget = AnalysisBudget {
+            max_tracked_blocks: 16,
+        };
+        let trace: Vec<u64> = patterns::sequential(0, 32, 1000).collect();
+        let run = |budget: Option<AnalysisBudget>| {
+            let mut cache =
+                CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).with_reuse_distances();
+            if let Some(budget) = budget {
+                cache = cache.with_analysis_budget(budget);
+            }
+            cache.enable_frequency_tracking();
+            cache.replay(&trace);
+            cache.replay(&trace[..8]);
+            cache.make_report()
+        };
+
+        let exact = run(None);
+        assert!(!exact.approximate());
+        assert_eq!(exact.top_tags(usize::MAX).unwrap().len(), 1000);
+
+        let bounded = run(Some(budget));
+        assert!(bounded.approximate());
+        let frequencies = bounded.top_tags(usize::MAX).unwrap();
+        assert_eq!(frequencies.len(), 16);
+        // the first blocks were tracked, so their counts are still right
+        assert_eq!(frequencies[0], (0, 2));
+        // the 992 blocks in between weren't counted towards the reuse distance
+        assert_eq!(exact.reuse_distance_percentile(1.0), Some(999));
+        assert_eq!(bounded.reuse_distance_percentile(1.0), Some(15));
+    }
+
+    #[test]
+    fn renumbered_marks() {
+        // far more accesses than blocks, the tracker has to keep renumbering them
+        let trace: Vec<u64> = patterns::random_uniform(0, 64 * 32, 20_000, 5).collect();
+        let tracker = ReuseTracker::new();
+        for &address in &trace {
+            tracker.record(address >> 5, true, usize::MAX);
+        }
+        let exact: Vec<u64> = reuse_distances(&trace, 5)
+            .into_iter()
+            .flatten()
+            .map(|d| d as u64)
+            .collect();
+        let mean = exact.iter().sum::<u64>() as f64 / exact.len() as f64;
+        assert_eq!(tracker.mean_hit_distance(), Some(mean));
+        assert_eq!(tracker.state.lock().unwrap().marks.len(), 1024);
+    }
+
+    #[test]
+    fn size_for_target() {
+        // ten laps over 64 blocks, LRU only hits once all 64 fit
+        let lap: Vec<u64> = (0..64).map(|i| 32 * i).collect();
+        let trace = lap.repeat(10);
+
+        let spec = size_for_hit_rate(&trace, 5, 1, 0.85);
+        assert_eq!(spec.size(), 64 * 32);
+        assert_eq!(spec.associativity(), 2);
+
+        let cache = CacheState::from_spec(spec);
+        cache.replay(&trace);
+        assert_eq!(cache.make_report().hits(), 9 * 64);
+
+        // unreachable targets get the cache that holds everything
+        assert_eq!(size_for_hit_rate(&trace, 5, 1, 0.99).size(), 64 * 32);
+    }
+
+    #[test]
+    fn warm_up_share() {
+        let spec = CacheSpec::spec_8kib_32bit_2way();
+        // a 4KiB working set read a few times over
+        let trace: Vec<u64> = patterns::sequential(0, 8, 512)
+            .collect::<Vec<_>>()
+            .repeat(3);
+        let (cold, warm) = cold_vs_warm(&spec, &trace);
+        assert_eq!(cold.access_count(), warm.access_count());
+        assert_eq!(cold.misses(), 128);
+        assert_eq!(warm.misses(), 0);
+
+        // a stream never gets warm
+        let stream: Vec<u64> = patterns::sequential(0, 32, 1024).collect();
+        let (cold, warm) = cold_vs_warm(&spec, &stream);
+        assert_eq!(cold.misses(), warm.misses());
+    }
+
+    #[test]
+    fn block_sizes() {
+        let misses = |trace: &[u64]| -> Vec<u64> {
+            block_size_sweep(trace, 6, 1, 4, 7)
+                .iter()
+                .map(|(_, report)| report.misses())
+                .collect()
+        };
+
+        let sequential: Vec<u64> = patterns::sequential(0, 8, 4096).collect();
+        assert_eq!(misses(&sequential), [2048, 1024, 512, 256]);
+
+        // blocks scattered over a gigabyte are never reused, however big they are
+        let random: Vec<u64> = patterns::random_uniform(0, 1 << 30, 4096, 1).collect();
+        assert!(misses(&random).iter().all(|&m| m > 4000));
+    }
+
+    #[test]
+    fn parallel_matches_sequential() {
+        let trace: Vec<u64> = patterns::random_uniform(0, 1 << 16, 20_000, 4).collect();
+        let specs: Vec<CacheSpec> = (5..9).map(|sets| CacheSpec::new(5, sets, 1)).collect();
+
+        let sequential: Vec<ThrasheReport> = specs
+            .iter()
+            .map(|spec| {
+                let cache = CacheState::from_spec(spec.clone());
+                cache.replay(&trace);
+                cache.make_report()
+            })
+            .collect();
+        assert_eq!(parallel_sweep(&specs, &trace), sequential);
+    }
+}
@@ -0,0 +1,183 @@
+use std::{fmt, sync::Mutex};
+
+use crate::thrashe::{CacheState, ThrasheReport};
+
+/// Caches that fetch their misses over one bus, which serializes the transfers so a miss waits
+/// for any other cache's transfer still on the bus
+///
+/// every access takes a cycle of its cache's own clock, and a miss then holds the bus for
+/// `miss_cycles`, stalling its cache until the transfer is done
+pub struct SharedBus {
+    caches: Vec<CacheState>,
+    miss_cycles: u64,
+    state: Mutex<BusState>,
+}
+
+#[derive(Default)]
+struct BusState {
+    /// the cycle each cache has got to
+    clocks: Vec<u64>,
+    stalls: Vec<u64>,
+    free_at: u64,
+    busy_cycles: u64,
+}
+
+impl SharedBus {
+    pub fn new(miss_cycles: u64) -> SharedBus {
+        assert!(miss_cycles > 0, "a transfer must take at least one cycle");
+        SharedBus {
+            caches: Vec::new(),
+            miss_cycles,
+            state: Mutex::new(BusState::default()),
+        }
+    }
+
+    /// adds a cache fetching over the bus, returning the id to touch it by
+    pub fn register(&mut self, cache: CacheState) -> usize {
+        let state = self.state.get_mut().unwrap();
+        state.clocks.push(0);
+        state.stalls.push(0);
+        self.caches.push(cache);
+        self.caches.len() - 1
+    }
+
+    /// the registered caches, indexed by id
+    pub fn caches(&self) -> &[CacheState] {
+        &self.caches
+    }
+
+    /// a read by cache `id`, returns whether it hit
+    pub fn touch_address(&self, id: usize, address: u64) -> bool {
+        let hit = self.caches[id].touch_address(address);
+        self.advance(id, hit);
+        hit
+    }
+
+    /// a write by cache `id`, returns whether it hit
+    pub fn touch_address_write(&self, id: usize, address: u64) -> bool {
+        let hit = self.caches[id].touch_address_write(address);
+        self.advance(id, hit);
+        hit
+    }
+
+    fn advance(&self, id: usize, hit: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.clocks[id] += 1;
+        if hit {
+            return;
+        }
+        let requested = state.clocks[id];
+        let start = requested.max(state.free_at);
+        state.stalls[id] += start - requested;
+        state.free_at = start + self.miss_cycles;
+        state.busy_cycles += self.miss_cycles;
+        state.clocks[id] = state.free_at;
+    }
+
+    pub fn make_report(&self) -> BusReport {
+        let state = self.state.lock().unwrap();
+        BusReport {
+            caches: self.caches.iter().map(|c| c.make_report()).collect(),
+            stalls: state.stalls.clone(),
+            busy_cycles: state.busy_cycles,
+            elapsed: state.clocks.iter().copied().max().unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BusReport {
+    caches: Vec<ThrasheReport>,
+    stalls: Vec<u64>,
+    busy_cycles: u64,
+    elapsed: u64,
+}
+
+impl BusReport {
+    /// one report per cache, indexed by id
+    pub fn caches(&self) -> &[ThrasheReport] {
+        &self.caches
+    }
+
+    /// cycles cache `id` spent waiting for another cache's transfer to leave the bus, not
+    /// counting its own transfers
+    pub fn contention_stalls(&self, id: usize) -> Option<u64> {
+        self.stalls.get(id).copied()
+    }
+
+    /// cycles the bus spent transferring misses
+    pub fn busy_cycles(&self) -> u64 {
+        self.busy_cycles
+    }
+
+    /// the cycle the furthest along cache got to
+    pub fn elapsed_cycles(&self) -> u64 {
+        self.elapsed
+    }
+
+    /// the share of the elapsed cycles the bus was busy, near 1 when it's the bottleneck
+    pub fn utilization(&self) -> f64 {
+        if self.elapsed == 0 {
+            return 0.0;
+        }
+        self.busy_cycles as f64 / self.elapsed as f64
+    }
+}
+
+impl fmt::Display for BusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (id, (report, stalls)) in self.caches.iter().zip(&self.stalls).enumerate() {
+            writeln!(f, "cache {id}:")?;
+            writeln!(f, "  accesses: {}", report.access_count())?;
+            writeln!(f, "  misses: {}", report.misses())?;
+            writeln!(f, "  contention stalls: {stalls}")?;
+        }
+        write!(
+            f,
+            "bus busy: {} of {} cycles ({:.1}%)",
+            self.busy_cycles,
+            self.elapsed,
+            self.utilization() * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thrashe::CacheSpec;
+
+    #[test]
+    fn saturated_bus() {
+        let mut bus = SharedBus::new(20);
+        for _ in 0..2 {
+            bus.register(CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()));
+        }
+        // both caches stream through far more than they hold, so every access misses
+        for i in 0..1000 {
+            bus.touch_address(0, i * 32);
+            bus.touch_address(1, (1 << 20) + i * 32);
+        }
+
+        let report = bus.make_report();
+        assert_eq!(report.caches()[0].misses(), 1000);
+        assert_eq!(report.busy_cycles(), 2000 * 20);
+        assert!(report.utilization() > 0.95, "{}", report.utilization());
+        // each waits out the other's transfer almost every time
+        assert!(report.contention_stalls(0).unwrap() > 0);
+        assert!(report.contention_stalls(1).unwrap() > 1000 * 15);
+        assert_eq!(report.contention_stalls(2), None);
+        assert!(report.to_string().contains("cache 1:\n  accesses: 1000"));
+
+        // a cache that hits never touches the bus
+        let mut bus = SharedBus::new(20);
+        let id = bus.register(CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()));
+        for _ in 0..10 {
+            bus.touch_address(id, 0);
+        }
+        let report = bus.make_report();
+        assert_eq!(report.busy_cycles(), 20);
+        assert_eq!(report.elapsed_cycles(), 30);
+        assert_eq!(report.contention_stalls(id), Some(0));
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    fmt,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::thrashe::{CacheSpec, CacheState, ThrasheReport};
+
+/// A private cache for each core, kept coherent by a write invalidating every other core's copy
+///
+/// with a shared llc every access takes a cycle of its core's own clock, and a private miss then
+/// holds the llc for [`CoherentCluster::with_llc_cycles`], stalling its core until the llc is
+/// done, like [`SharedBus`]. The cores get the llc in the order their accesses are made, so the
+/// contention only depends on that order
+///
+/// [`SharedBus`]: crate::bus::SharedBus
+pub struct CoherentCluster {
+    cores: Vec<CacheState>,
+    invalidations: AtomicU64,
+    /// consulted whenever a core's private cache misses
+    llc: Option<CacheState>,
+    llc_cycles: u64,
+    llc_clock: Mutex<LlcClock>,
+}
+
+#[derive(Default)]
+struct LlcClock {
+    /// the cycle each core has got to
+    clocks: Vec<u64>,
+    free_at: u64,
+    contention: u64,
+}
+
+impl CoherentCluster {
+    /// `cores` cores, each with its own cache of `spec`
+    pub fn new(cores: usize, spec: CacheSpec) -> CoherentCluster {
+        CoherentCluster {
+            cores: (0..cores)
+                .map(|_| CacheState::from_spec(spec.clone()))
+                .collect(),
+            invalidations: AtomicU64::new(0),
+            llc: None,
+            llc_cycles: 1,
+            llc_clock: Mutex::new(LlcClock {
+                clocks: vec![0; cores],
+                ..LlcClock::default()
+            }),
+        }
+    }
+
+    /// adds a last level cache of `spec` shared by all the cores
+    pub fn with_shared_llc(mut self, spec: CacheSpec) -> CoherentCluster {
+        self.llc = Some(CacheState::from_spec(spec));
+        self
+    }
+
+    /// how long an access holds the llc, 1 cycle unless set
+    pub fn with_llc_cycles(mut self, cycles: u64) -> CoherentCluster {
+        assert!(cycles > 0, "an llc access must take at least one cycle");
+        self.llc_cycles = cycles;
+        self
+    }
+
+    /// the private caches, indexed by core id
+    pub fn cores(&self) -> &[CacheState] {
+        &self.cores
+    }
+
+    pub fn llc(&self) -> Option<&CacheState> {
+        self.llc.as_ref()
+    }
+
+    /// a read by `core`, returns whether its private cache hit
+    pub fn touch_address(&self, core: usize, address: u64) -> bool {
+        let hit = self.cores[core].touch_address(address);
+        self.touch_llc(core, hit, address);
+        hit
+    }
+
+    /// moves `core`'s clock on by the access, and after a private miss by its turn in the llc
+    fn touch_llc(&self, core: usize, hit: bool, address: u64) {
+        let Some(llc) = &self.llc else { return };
+        let mut clock = self.llc_clock.lock().unwrap();
+        clock.clocks[core] += 1;
+        if hit {
+            return;
+        }
+        let requested = clock.clocks[core];
+        if clock.free_at > requested {
+            clock.contention += 1;
+        }
+        let start = requested.max(clock.free_at);
+        clock.free_at = start + self.llc_cycles;
+        clock.clocks[core] = clock.free_at;
+        llc.touch_address(address);
+    }
+
+    /// a write by `core`, which first takes the block away from every other core
+    pub fn touch_address_write(&self, core: usize, address: u64) -> bool {
+        for (other, cache) in self.cores.iter().enumerate() {
+            if other != core && cache.invalidate(address) {
+                self.invalidations.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let hit = self.cores[core].touch_address_write(address);
+        self.touch_llc(core, hit, address);
+        hit
+    }
+
+    pub fn make_report(&self) -> ClusterReport {
+        ClusterReport {
+            cores: self.cores.iter().map(|c| c.make_report()).collect(),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            llc: self.llc.as_ref().map(|l| l.make_report()),
+            llc_contention: self.llc_clock.lock().unwrap().contention,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClusterReport {
+    cores: Vec<ThrasheReport>,
+    invalidations: u64,
+    llc: Option<ThrasheReport>,
+    llc_contention: u64,
+}
+
+impl ClusterReport {
+    /// one report per core, indexed by core id
+    pub fn cores(&self) -> &[ThrasheReport] {
+        &self.cores
+    }
+
+    pub fn core(&self, core_id: usize) -> Option<&ThrasheReport> {
+        self.cores.get(core_id)
+    }
+
+    /// copies dropped from a core's cache because another core wrote to the block
+    pub fn invalidations(&self) -> u64 {
+        self.invalidations
+    }
+
+    /// only present if the cluster was created [`CoherentCluster::with_shared_llc`]
+    pub fn llc(&self) -> Option<&ThrasheReport> {
+        self.llc.as_ref()
+    }
+
+    /// llc accesses that found it busy with another core's and waited
+    pub fn llc_contention(&self) -> u64 {
+        self.llc_contention
+    }
+}
+
+impl fmt::Display for ClusterReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let section = |f: &mut fmt::Formatter<'_>, label: &str, report: &ThrasheReport| {
+            writeln!(f, "{label}:")?;
+            writeln!(f, "  accesses: {}", report.access_count())?;
+            writeln!(f, "  hits: {}", report.hits())?;
+            writeln!(f, "  misses: {}", report.misses())
+        };
+        for (core_id, report) in self.cores.iter().enumerate() {
+            section(f, &format!("core {core_id}"), report)?;
+        }
+        if let Some(llc) = &self.llc {
+            section(f, "llc", llc)?;
+            writeln!(f, "  contention: {}", self.llc_contention)?;
+        }
+        write!(f, "invalidations: {}", self.invalidations)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn labeled_cores() {
+        let cluster = CoherentCluster::new(2, CacheSpec::spec_8kib_32bit_2way());
+        for _ in 0..3 {
+            cluster.touch_address(0, 0);
+        }
+        for i in 0..4 {
+            cluster.touch_address_write(1, 4096 + 64 * i);
+        }
+
+        let report = cluster.make_report();
+        assert_eq!(report.core(0).unwrap().access_count(), 3);
+        assert_eq!(report.core(0).unwrap().hits(), 2);
+        assert_eq!(report.core(1).unwrap().access_count(), 4);
+        assert_eq!(report.core(1).unwrap().misses(), 4);
+        assert_eq!(report.invalidations(), 0);
+        assert!(report.core(2).is_none());
+
+        let text = report.to_string();
+        let core_1 = text.find("core 1:").unwrap();
+        assert!(text[..core_1].contains("hits: 2"));
+        assert!(text[core_1..].contains("misses: 4"));
+        assert!(text.ends_with("invalidations: 0"));
+
+        // writing to the block core 0 is reading takes it away
+        cluster.touch_address_write(1, 0);
+        assert!(!cluster.cores()[0].contains(0));
+        assert_eq!(cluster.make_report().invalidations(), 1);
+    }
+
+    #[test]
+    fn shared_llc() {
+        // 4KiB of shared data, too much for either core's 256 bytes but not for the llc
+        let cluster = CoherentCluster::new(2, CacheSpec::new(5, 2, 1))
+            .with_shared_llc(CacheSpec::spec_8kib_32bit_2way())
+            .with_llc_cycles(4);
+        let data: Vec<u64> = (0..128).map(|b| b * 32).collect();
+        for &address in &data {
+            cluster.touch_address(0, address);
+        }
+        // a core on its own never waits for the llc
+        assert_eq!(cluster.make_report().llc_contention(), 0);
+
+        // the other core finds everything the first brought in, but as its clock started at 0
+        // too its first access waits for all of the first core's
+        for &address in &data {
+            cluster.touch_address(1, address);
+        }
+        let report = cluster.make_report();
+        assert_eq!(report.cores()[1].misses(), 128);
+        let llc = report.llc().unwrap();
+        assert_eq!(llc.misses(), 128);
+        assert_eq!(llc.hits(), 128);
+        assert_eq!(report.invalidations(), 0);
+        assert_eq!(report.llc_contention(), 1);
+        assert!(report.to_string().contains("llc:\n  accesses: 256"));
+
+        // in step, every access finds the other core's still in the llc
+        for &address in &data {
+            for core in 0..2 {
+                cluster.touch_address(core, address);
+            }
+        }
+        let report = cluster.make_report();
+        assert_eq!(report.llc_contention(), 1 + 256);
+        assert!(report.to_string().contains("contention: 257"));
+    }
+}
@@ -0,0 +1,126 @@
+//! Counters for a cache's hit and miss totals, picked by how they should overflow
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// A shared event counter, see [`crate::thrashe::CacheState::from_spec_with_counters`]
+pub trait Counter: Send + Sync {
+    fn increment(&self);
+    fn get(&self) -> u64;
+    fn reset(&self);
+}
+
+/// wraps back to zero
+impl Counter for AtomicU32 {
+    fn increment(&self) {
+        self.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.load(Ordering::Relaxed) as u64
+    }
+
+    fn reset(&self) {
+        self.store(0, Ordering::Relaxed);
+    }
+}
+
+/// too wide to overflow in practice, the default
+impl Counter for AtomicU64 {
+    fn increment(&self) {
+        self.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.load(Ordering::Relaxed)
+    }
+
+    fn reset(&self) {
+        self.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Sticks at `u32::MAX` rather than wrapping
+#[derive(Debug, Default)]
+pub struct SaturatingU32(AtomicU32);
+
+impl SaturatingU32 {
+    pub fn new(value: u32) -> SaturatingU32 {
+        SaturatingU32(AtomicU32::new(value))
+    }
+}
+
+impl Counter for SaturatingU32 {
+    fn increment(&self) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| v.checked_add(1));
+    }
+
+    fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    fn reset(&self) {
+        self.0.reset();
+    }
+}
+
+/// Panics on overflow, for runs long enough that a wrapped count would go unnoticed
+#[derive(Debug, Default)]
+pub struct CheckedU32(AtomicU32);
+
+impl CheckedU32 {
+    pub fn new(value: u32) -> CheckedU32 {
+        CheckedU32(AtomicU32::new(value))
+    }
+}
+
+impl Counter for CheckedU32 {
+    fn increment(&self) {
+        if self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| v.checked_add(1))
+            .is_err()
+        {
+            panic!("cache counter overflowed u32");
+        }
+    }
+
+    fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    fn reset(&self) {
+        self.0.reset();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thrashe::{CacheSpec, CacheState};
+
+    #[test]
+    fn overflow_behaviour() {
+        let saturating = SaturatingU32::new(u32::MAX - 1);
+        saturating.increment();
+        saturating.increment();
+        saturating.increment();
+        assert_eq!(saturating.get(), u32::MAX as u64);
+        saturating.reset();
+        assert_eq!(saturating.get(), 0);
+
+        let wrapping = AtomicU32::new(u32::MAX);
+        wrapping.increment();
+        assert_eq!(wrapping.get(), 0);
+
+        let checked = CheckedU32::new(u32::MAX);
+        assert!(std::panic::catch_unwind(|| checked.increment()).is_err());
+
+        let cache =
+            CacheState::<SaturatingU32>::from_spec_with_counters(CacheSpec::spec_8kib_32bit_2way());
+        cache.replay(&[0, 0, 32]);
+        let report = cache.make_report();
+        assert_eq!((report.hits(), report.misses()), (1, 2));
+    }
+}
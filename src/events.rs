@@ -0,0 +1,57 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheEvent {
+    Hit {
+        address: u64,
+    },
+    Miss {
+        address: u64,
+    },
+    /// `block` is the block aligned address of the block that was replaced
+    Eviction {
+        block: u64,
+    },
+}
+
+/// The most recent events, dropping the oldest once `capacity` is reached
+pub(crate) struct EventBuffer {
+    capacity: usize,
+    state: Mutex<EventState>,
+}
+
+struct EventState {
+    events: VecDeque<CacheEvent>,
+    dropped: u64,
+}
+
+impl EventBuffer {
+    pub fn new(capacity: usize) -> EventBuffer {
+        EventBuffer {
+            capacity,
+            state: Mutex::new(EventState {
+                events: VecDeque::with_capacity(capacity),
+                dropped: 0,
+            }),
+        }
+    }
+
+    pub fn record(&self, event: CacheEvent) {
+        let mut state = self.state.lock().unwrap();
+        if self.capacity == 0 {
+            state.dropped += 1;
+            return;
+        }
+        if state.events.len() == self.capacity {
+            state.events.pop_front();
+            state.dropped += 1;
+        }
+        state.events.push_back(event);
+    }
+
+    /// oldest first, along with how many events have been dropped
+    pub fn snapshot(&self) -> (Vec<CacheEvent>, u64) {
+        let state = self.state.lock().unwrap();
+        (state.events.iter().copied().collect(), state.dropped)
+    }
+}
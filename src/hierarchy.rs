@@ -0,0 +1,511 @@
+use std::{
+    error::Error,
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::{
+    replacement::ReplacementPolicy,
+    thrashe::{CacheSpec, CacheState, ThrasheReport},
+};
+
+/// How the contents of a level relate to the levels below it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inclusion {
+    /// every block in a level is also held by all the levels below it, a block evicted from a
+    /// lower level is back-invalidated in the levels above
+    Inclusive,
+    /// blocks are filled into every level on a miss, but nothing is enforced afterwards
+    NonInclusive,
+    /// blocks live in exactly one level, lower levels act as victim caches for the level above
+    Exclusive,
+}
+
+/// Several caches where each level is only consulted when the level above it misses
+pub struct CacheHierarchy {
+    levels: Vec<CacheState>,
+    /// whether `levels[0]` is a filter from [`HierarchyBuilder::filter`]
+    filter: bool,
+    inclusion: Inclusion,
+    promotions: AtomicU32,
+    demotions: AtomicU32,
+    back_invalidations: AtomicU32,
+}
+
+impl CacheHierarchy {
+    pub fn levels(&self) -> &[CacheState] {
+        &self.levels
+    }
+
+    pub fn inclusion(&self) -> Inclusion {
+        self.inclusion
+    }
+
+    pub fn has_filter(&self) -> bool {
+        self.filter
+    }
+
+    /// returns the index of the level that hit, or `None` if it went to memory
+    pub fn touch_address(&self, address: u64) -> Option<usize> {
+        match self.inclusion {
+            Inclusion::Inclusive => self.touch_inclusive(address),
+            // a missing level fills the block on the way back, so walking down is enough
+            Inclusion::NonInclusive => self
+                .levels
+                .iter()
+                .position(|level| level.touch_address(address)),
+            Inclusion::Exclusive => self.touch_exclusive(address),
+        }
+    }
+
+    fn touch_inclusive(&self, address: u64) -> Option<usize> {
+        for (i, level) in self.levels.iter().enumerate() {
+            let access = level.access(address, false, true);
+            if let Some(block) = access.evicted {
+                self.back_invalidate(i, block);
+            }
+            if access.hit {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// drops every part of `block`, just evicted from `level`, from the levels above it
+    fn back_invalidate(&self, level: usize, block: u64) {
+        let block_size = self.levels[level].spec().block_size() as u64;
+        for above in &self.levels[..level] {
+            let step = above.spec().block_size() as u64;
+            for address in (block..block + block_size).step_by(step as usize) {
+                if above.invalidate(address) {
+                    self.back_invalidations.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    fn touch_exclusive(&self, address: u64) -> Option<usize> {
+        let top = self.levels[0].access(address, false, true);
+        if top.hit {
+            return Some(0);
+        }
+
+        // the block moves up out of whichever lower level holds it
+        let mut hit_level = None;
+        for (i, level) in self.levels.iter().enumerate().skip(1) {
+            if level.access(address, false, false).hit {
+                level.invalidate(address);
+                self.promotions.fetch_add(1, Ordering::Relaxed);
+                hit_level = Some(i);
+                break;
+            }
+        }
+
+        // and whatever it displaced trickles down, falling out of the last level
+        let mut victim = top.evicted;
+        for level in &self.levels[1..] {
+            let Some(block) = victim else { break };
+            self.demotions.fetch_add(1, Ordering::Relaxed);
+            victim = level.fill(block);
+        }
+
+        hit_level
+    }
+
+    pub fn replay(&self, trace: &[u64]) {
+        for &address in trace {
+            self.touch_address(address);
+        }
+    }
+
+    pub fn make_report(&self) -> HierarchyReport {
+        HierarchyReport {
+            levels: self.levels.iter().map(|l| l.make_report()).collect(),
+            filter: self.filter,
+            inclusion: self.inclusion,
+            promotions: self.promotions.load(Ordering::Relaxed),
+            demotions: self.demotions.load(Ordering::Relaxed),
+            back_invalidations: self.back_invalidations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HierarchyReport {
+    levels: Vec<ThrasheReport>,
+    filter: bool,
+    inclusion: Inclusion,
+    promotions: u32,
+    demotions: u32,
+    back_invalidations: u32,
+}
+
+impl HierarchyReport {
+    /// one report per level, starting at the level closest to the core
+    pub fn levels(&self) -> &[ThrasheReport] {
+        &self.levels
+    }
+
+    /// the replacement policy of each level, starting at the level closest to the core
+    pub fn policies(&self) -> Vec<ReplacementPolicy> {
+        self.levels.iter().map(|l| l.spec().policy()).collect()
+    }
+
+    /// the filter in front of the first level, the same as `levels()[0]` when there is one
+    pub fn filter(&self) -> Option<&ThrasheReport> {
+        self.filter.then(|| &self.levels[0])
+    }
+
+    pub fn inclusion(&self) -> Inclusion {
+        self.inclusion
+    }
+
+    /// misses of each level over the accesses that reached it, 0 for a level nothing reached
+    pub fn local_miss_rates(&self) -> Vec<f64> {
+        self.levels
+            .iter()
+            .map(|l| {
+                if l.access_count() == 0 {
+                    0.0
+                } else {
+                    l.misses() as f64 / l.access_count() as f64
+                }
+            })
+            .collect()
+    }
+
+    /// fraction of all accesses that went to memory, the product of the local miss rates
+    pub fn global_miss_rate(&self) -> f64 {
+        self.local_miss_rates().iter().product()
+    }
+
+    /// average memory access time, where `latencies` are the hit latencies of each level and
+    /// `mem_latency` that of memory
+    ///
+    /// each level's local miss rate weighs the time spent in the levels below it, so
+    /// `l1 + m1 * (l2 + m2 * mem)` for two levels
+    pub fn amat(&self, latencies: &[f64], mem_latency: f64) -> f64 {
+        assert_eq!(
+            latencies.len(),
+            self.levels.len(),
+            "need a latency for every level"
+        );
+        latencies
+            .iter()
+            .zip(self.local_miss_rates())
+            .rev()
+            .fold(mem_latency, |below, (latency, miss_rate)| {
+                latency + miss_rate * below
+            })
+    }
+
+    /// blocks moved up to the first level by a hit in a lower one, only under [`Inclusion::Exclusive`]
+    pub fn promotions(&self) -> u32 {
+        self.promotions
+    }
+
+    /// evicted blocks moved down a level, only under [`Inclusion::Exclusive`]
+    pub fn demotions(&self) -> u32 {
+        self.demotions
+    }
+
+    /// lines dropped from a level because a lower one evicted their block, only under
+    /// [`Inclusion::Inclusive`]
+    pub fn back_invalidations(&self) -> u32 {
+        self.back_invalidations
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HierarchyError {
+    NoLevels,
+    /// `level` holds fewer bytes than the level above it
+    SmallerThanAbove {
+        level: usize,
+        size: u64,
+        above: u64,
+    },
+}
+
+impl fmt::Display for HierarchyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HierarchyError::NoLevels => write!(f, "a cache hierarchy needs at least one level"),
+            HierarchyError::SmallerThanAbove { level, size, above } => write!(
+                f,
+                "level {level} ({size} bytes) is smaller than the level above it ({above} bytes)"
+            ),
+        }
+    }
+}
+
+impl Error for HierarchyError {}
+
+/// Builds a [`CacheHierarchy`] from the level closest to the core outwards
+#[derive(Debug, Clone)]
+pub struct HierarchyBuilder {
+    levels: Vec<CacheSpec>,
+    filter: Option<usize>,
+    inclusion: Inclusion,
+}
+
+impl Default for HierarchyBuilder {
+    fn default() -> Self {
+        HierarchyBuilder::new()
+    }
+}
+
+impl HierarchyBuilder {
+    pub fn new() -> HierarchyBuilder {
+        HierarchyBuilder {
+            levels: Vec::new(),
+            filter: None,
+            inclusion: Inclusion::NonInclusive,
+        }
+    }
+
+    /// adds the next level out, either a spec or a `(spec, policy)` pair to use a different
+    /// replacement policy than the spec's
+    pub fn level(mut self, level: impl Into<CacheSpec>) -> HierarchyBuilder {
+        self.levels.push(level.into());
+        self
+    }
+
+    /// puts a tiny fully associative cache of `lines` lines in front of the first level, like a
+    /// loop buffer, so accesses it catches never reach the first level
+    ///
+    /// it becomes level 0 and shares the first level's block size
+    pub fn filter(mut self, lines: usize) -> HierarchyBuilder {
+        self.filter = Some(lines);
+        self
+    }
+
+    pub fn inclusion(mut self, inclusion: Inclusion) -> HierarchyBuilder {
+        self.inclusion = inclusion;
+        self
+    }
+
+    pub fn build(self) -> Result<CacheHierarchy, HierarchyError> {
+        if self.levels.is_empty() {
+            return Err(HierarchyError::NoLevels);
+        }
+        for (level, pair) in self.levels.windows(2).enumerate() {
+            let (above, size) = (pair[0].size(), pair[1].size());
+            if size < above {
+                return Err(HierarchyError::SmallerThanAbove {
+                    level: level + 1,
+                    size,
+                    above,
+                });
+            }
+        }
+
+        let filter = self
+            .filter
+            .map(|lines| CacheSpec::with_ways(self.levels[0].block_size_bits(), 0, lines));
+        Ok(CacheHierarchy {
+            filter: filter.is_some(),
+            levels: filter
+                .into_iter()
+                .chain(self.levels)
+                .map(CacheState::from_spec)
+                .collect(),
+            inclusion: self.inclusion,
+            promotions: AtomicU32::new(0),
+            demotions: AtomicU32::new(0),
+            back_invalidations: AtomicU32::new(0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn l2() -> CacheSpec {
+        CacheSpec::new(5, 9, 2)
+    }
+
+    fn l3() -> CacheSpec {
+        CacheSpec::new(6, 12, 3)
+    }
+
+    #[test]
+    fn three_levels() {
+        let hierarchy = HierarchyBuilder::new()
+            .level(CacheSpec::spec_8kib_32bit_2way())
+            .level(l2())
+            .level(l3())
+            .inclusion(Inclusion::Inclusive)
+            .build()
+            .unwrap();
+        assert_eq!(hierarchy.levels().len(), 3);
+        assert_eq!(hierarchy.inclusion(), Inclusion::Inclusive);
+
+        assert_eq!(hierarchy.touch_address(4200), None);
+        assert_eq!(hierarchy.touch_address(4200), Some(0));
+
+        let report = hierarchy.make_report();
+        let accesses: Vec<u64> = report.levels().iter().map(|l| l.access_count()).collect();
+        assert_eq!(accesses, [2, 1, 1]);
+    }
+
+    #[test]
+    fn two_level_amat() {
+        let hierarchy = HierarchyBuilder::new()
+            .level(CacheSpec::spec_8kib_32bit_2way())
+            .level(l2())
+            .build()
+            .unwrap();
+        // two laps over 16KiB, too much for L1 but the second lap hits in L2
+        let lap: Vec<u64> = (0..512).map(|b| b * 32).collect();
+        hierarchy.replay(&lap.repeat(2));
+
+        let report = hierarchy.make_report();
+        assert_eq!(report.local_miss_rates(), [1.0, 0.5]);
+        assert_eq!(report.global_miss_rate(), 0.5);
+        assert_eq!(
+            report.amat(&[1.0, 10.0], 100.0),
+            1.0 + 1.0 * (10.0 + 0.5 * 100.0)
+        );
+    }
+
+    #[test]
+    fn victim_cache() {
+        // two blocks fighting over a direct mapped set, the victim level catches the loser
+        let hierarchy = HierarchyBuilder::new()
+            .level(CacheSpec::new(5, 7, 0))
+            .level(CacheSpec::spec_8kib_32bit_2way())
+            .inclusion(Inclusion::Exclusive)
+            .build()
+            .unwrap();
+        hierarchy.replay(&[0, 4096]);
+        assert_eq!(hierarchy.touch_address(0), Some(1));
+        assert_eq!(hierarchy.touch_address(4096), Some(1));
+        assert_eq!(hierarchy.touch_address(0), Some(1));
+
+        // exclusive, so each block is only ever in one level
+        assert!(hierarchy.levels()[0].contains(0));
+        assert!(!hierarchy.levels()[1].contains(0));
+        assert!(hierarchy.levels()[1].contains(4096));
+
+        let report = hierarchy.make_report();
+        assert_eq!(report.promotions(), 3);
+        assert_eq!(report.demotions(), 4);
+        assert_eq!(report.levels()[0].hits(), 0);
+        assert_eq!(report.levels()[1].hits(), 3);
+    }
+
+    #[test]
+    fn back_invalidation() {
+        // a fully associative L1 could keep both blocks, but they fight over a direct mapped L2
+        let build = |inclusion| {
+            HierarchyBuilder::new()
+                .level(CacheSpec::with_ways(5, 0, 8))
+                .level(CacheSpec::new(5, 3, 0))
+                .inclusion(inclusion)
+                .build()
+                .unwrap()
+        };
+        let trace = [0, 256].repeat(10);
+
+        let inclusive = build(Inclusion::Inclusive);
+        inclusive.replay(&trace);
+        let non_inclusive = build(Inclusion::NonInclusive);
+        non_inclusive.replay(&trace);
+        let (inclusive, non_inclusive) = (inclusive.make_report(), non_inclusive.make_report());
+
+        assert_eq!(non_inclusive.levels()[0].misses(), 2);
+        assert_eq!(non_inclusive.back_invalidations(), 0);
+        // every L2 fill after the first throws the other block out of L1 too
+        assert_eq!(inclusive.levels()[0].misses(), 20);
+        assert_eq!(inclusive.back_invalidations(), 19);
+    }
+
+    #[test]
+    fn loop_filter() {
+        let hierarchy = HierarchyBuilder::new()
+            .filter(4)
+            .level(CacheSpec::spec_8kib_32bit_2way())
+            .level(l2())
+            .build()
+            .unwrap();
+        assert!(hierarchy.has_filter());
+        for _ in 0..500 {
+            hierarchy.replay(&[4096, 4104]);
+        }
+
+        let report = hierarchy.make_report();
+        let filter = report.filter().unwrap();
+        assert_eq!(filter.access_count(), 1000);
+        assert!(filter.hit_rate() > 0.99);
+        // only the very first touch got past the filter
+        assert_eq!(report.levels()[1].access_count(), 1);
+        assert!(
+            HierarchyBuilder::new()
+                .level(l2())
+                .build()
+                .unwrap()
+                .make_report()
+                .filter()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn per_level_policies() {
+        let llc = CacheSpec::new(6, 7, 3);
+        let hierarchy = |llc_policy| {
+            HierarchyBuilder::new()
+                .level((CacheSpec::spec_8kib_32bit_2way(), ReplacementPolicy::Lru))
+                .level((llc.clone(), llc_policy))
+                .build()
+                .unwrap()
+        };
+
+        // half the llc is reused between streams that are never touched again
+        let mut trace = Vec::new();
+        for lap in 0..30 {
+            trace.extend((0..512).map(|b| b * 64));
+            trace.extend((0..2048).map(|b| (1_000_000 + lap * 2048 + b) * 64));
+        }
+
+        let lru = hierarchy(ReplacementPolicy::Lru);
+        lru.replay(&trace);
+        let bip = hierarchy(ReplacementPolicy::Bip);
+        bip.replay(&trace);
+        let (lru, bip) = (lru.make_report(), bip.make_report());
+        assert_eq!(
+            bip.policies(),
+            [ReplacementPolicy::Lru, ReplacementPolicy::Bip]
+        );
+
+        // the lru first level is flushed by every stream either way, while the bip llc ends up
+        // keeping the reused blocks
+        assert_eq!(lru.levels()[0], bip.levels()[0]);
+        assert_eq!(lru.levels()[1].hits(), 0);
+        assert!(bip.levels()[1].hits() > 30 * 512 / 2);
+    }
+
+    #[test]
+    fn misordered_levels() {
+        let err = HierarchyBuilder::new()
+            .level(l2())
+            .level(CacheSpec::spec_8kib_32bit_2way())
+            .build()
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            HierarchyError::SmallerThanAbove {
+                level: 1,
+                size: 8192,
+                above: 65536,
+            }
+        );
+        assert_eq!(
+            HierarchyBuilder::new().build().err(),
+            Some(HierarchyError::NoLevels)
+        );
+    }
+}
@@ -1,4 +1,22 @@
+pub mod analysis;
+pub mod bus;
+pub mod cluster;
+pub mod counter;
+pub mod events;
+pub mod hierarchy;
+pub mod lifetime;
+pub mod memtrace;
+pub mod mshr;
+pub(crate) mod oracle;
+pub mod patterns;
 pub mod provider;
+pub mod replacement;
+pub mod temporal;
+pub mod testing;
 pub mod thrashe;
+pub mod timeline;
+pub mod write;
 
 pub use thrashe::Thrashe;
+#[cfg(feature = "derive")]
+pub use thrashe_derive::Thrashe;
@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Epoch each line was filled at, so its age can be taken when it's evicted
+pub(crate) struct LifetimeTracker {
+    filled_at: Vec<AtomicU32>,
+    ways: usize,
+    buckets: Vec<AtomicU64>,
+}
+
+impl LifetimeTracker {
+    pub fn new(set_num: usize, ways: usize) -> LifetimeTracker {
+        LifetimeTracker {
+            filled_at: (0..set_num * ways).map(|_| AtomicU32::new(0)).collect(),
+            ways,
+            buckets: (0..u32::BITS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// a block was filled at `epoch`, `evicted` is whether a valid block was replaced
+    pub fn fill(&self, set: usize, way: usize, epoch: u32, evicted: bool) {
+        let filled_at = self.filled_at[set * self.ways + way].swap(epoch, Ordering::Relaxed);
+        if evicted {
+            // the epoch wraps, an age is still the distance forward from its fill
+            let age = epoch.wrapping_sub(filled_at);
+            self.buckets[LifetimeHistogram::bucket_of(age)].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn snapshot(&self) -> LifetimeHistogram {
+        LifetimeHistogram {
+            buckets: self
+                .buckets
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+}
+
+/// How many accesses evicted lines survived for, in power of two buckets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LifetimeHistogram {
+    buckets: Vec<u64>,
+}
+
+impl LifetimeHistogram {
+    fn bucket_of(age: u32) -> usize {
+        age.max(1).ilog2() as usize
+    }
+
+    /// bucket `i` counts ages in `2^i..2^(i+1)`, except bucket 0 which also counts an age of 0
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// inclusive range of ages counted by bucket `i`
+    pub fn bucket_range(i: usize) -> (u32, u32) {
+        let low = if i == 0 { 0 } else { 1 << i };
+        (low, (1u64 << (i + 1)) as u32 - 1)
+    }
+
+    /// number of evictions recorded
+    pub fn evictions(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wrapped_epoch() {
+        let tracker = LifetimeTracker::new(1, 1);
+        tracker.fill(0, 0, u32::MAX - 1, false);
+        tracker.fill(0, 0, 2, true);
+        let histogram = tracker.snapshot();
+        assert_eq!(histogram.evictions(), 1);
+        // an age of 4
+        assert_eq!(histogram.buckets()[2], 1);
+    }
+}
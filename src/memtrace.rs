@@ -0,0 +1,202 @@
+//! Replaying binary memory traces such as the ones DynamoRIO's memtrace client writes
+//!
+//! a trace is a sequence of 10 byte records: `is_write: u8`, `size: u8`, then a little endian
+//! `addr: u64`
+//!
+//! page traces, like the ones built from `mincore` or `/proc/<pid>/pagemap` samples, are just
+//! little endian `u64` page numbers
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, ErrorKind, Read},
+};
+
+use crate::thrashe::CacheState;
+
+const RECORD_LEN: usize = 10;
+const PAGE_RECORD_LEN: usize = 8;
+
+#[derive(Debug)]
+pub enum MemtraceError {
+    Io(io::Error),
+    /// the access size of record `record` wasn't a power of two up to 64 bytes
+    UnknownSize {
+        record: usize,
+        size: u8,
+    },
+    /// the trace ended part way through record `record`
+    Truncated {
+        record: usize,
+    },
+}
+
+impl fmt::Display for MemtraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemtraceError::Io(e) => write!(f, "failed to read memtrace: {e}"),
+            MemtraceError::UnknownSize { record, size } => {
+                write!(
+                    f,
+                    "record {record} has an unknown access size of {size} bytes"
+                )
+            }
+            MemtraceError::Truncated { record } => {
+                write!(f, "memtrace ends part way through record {record}")
+            }
+        }
+    }
+}
+
+impl Error for MemtraceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MemtraceError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MemtraceError {
+    fn from(e: io::Error) -> Self {
+        MemtraceError::Io(e)
+    }
+}
+
+/// fills as much of `buf` as the reader has left, returning how many bytes were read
+fn read_record<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+impl CacheState {
+    /// touches every block each record's access covers, as a write if the record's `is_write` is set
+    ///
+    /// returns the number of records replayed
+    pub fn replay_memtrace<R: Read>(&self, mut reader: R) -> Result<usize, MemtraceError> {
+        let block_size = self.spec().block_size() as u64;
+        let mut buf = [0; RECORD_LEN];
+        let mut record = 0;
+        loop {
+            match read_record(&mut reader, &mut buf)? {
+                0 => return Ok(record),
+                RECORD_LEN => {}
+                _ => return Err(MemtraceError::Truncated { record }),
+            }
+
+            let (is_write, size) = (buf[0] != 0, buf[1]);
+            if !size.is_power_of_two() || size > 64 {
+                return Err(MemtraceError::UnknownSize { record, size });
+            }
+            let address = u64::from_le_bytes(buf[2..].try_into().unwrap());
+
+            let last = address.saturating_add(size as u64 - 1);
+            let mut block = address & !(block_size - 1);
+            while block <= last {
+                if is_write {
+                    self.touch_address_write(block);
+                } else {
+                    self.touch_address(block);
+                }
+                block += block_size;
+            }
+            record += 1;
+        }
+    }
+
+    /// touches every block of each page in a page trace, pages being `2^page_bits` bytes, as if
+    /// each page was read from start to end
+    ///
+    /// returns the number of pages replayed
+    pub fn replay_mmap_accesses<R: Read>(
+        &self,
+        mut reader: R,
+        page_bits: u8,
+    ) -> Result<usize, MemtraceError> {
+        let mut buf = [0; PAGE_RECORD_LEN];
+        let mut record = 0;
+        loop {
+            match read_record(&mut reader, &mut buf)? {
+                0 => return Ok(record),
+                PAGE_RECORD_LEN => {}
+                _ => return Err(MemtraceError::Truncated { record }),
+            }
+            let page = u64::from_le_bytes(buf);
+            self.touch_range(page << page_bits, 1 << page_bits);
+            record += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{thrashe::CacheSpec, write::WritePolicy};
+
+    fn record(is_write: bool, size: u8, address: u64) -> Vec<u8> {
+        let mut bytes = vec![is_write as u8, size];
+        bytes.extend(address.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn binary_records() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way())
+            .with_write_policy(WritePolicy::WriteBack);
+        let trace: Vec<u8> = [
+            record(false, 8, 0),
+            record(true, 4, 8),
+            // straddles the first two blocks
+            record(false, 8, 28),
+            record(false, 8, 4096),
+            record(false, 8, 8192),
+        ]
+        .concat();
+        assert_eq!(cache.replay_memtrace(trace.as_slice()).unwrap(), 5);
+
+        let report = cache.make_report();
+        assert_eq!(report.access_count(), 6);
+        assert_eq!(report.hits(), 2);
+        // the dirty block at 0 was the older of the two in its set
+        assert_eq!(report.writebacks(), Some(1));
+        assert!(!cache.contains(0));
+    }
+
+    #[test]
+    fn page_records() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        let trace: Vec<u8> = [3u64, 7, 3].iter().flat_map(|p| p.to_le_bytes()).collect();
+        assert_eq!(cache.replay_mmap_accesses(trace.as_slice(), 12).unwrap(), 3);
+
+        let report = cache.make_report();
+        let blocks_per_page = 4096 / 32;
+        assert_eq!(report.access_count(), 3 * blocks_per_page);
+        // the two pages fit in the cache together, so page 3 is still there the second time
+        assert_eq!(report.hits(), blocks_per_page);
+
+        let err = cache.replay_mmap_accesses(&trace[..12], 12).unwrap_err();
+        assert!(matches!(err, MemtraceError::Truncated { record: 1 }));
+    }
+
+    #[test]
+    fn bad_records() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        let trace = [record(false, 8, 0), record(false, 3, 0)].concat();
+        assert!(matches!(
+            cache.replay_memtrace(trace.as_slice()),
+            Err(MemtraceError::UnknownSize { record: 1, size: 3 })
+        ));
+
+        let trace = &record(false, 8, 0)[..7];
+        let err = cache.replay_memtrace(trace).unwrap_err();
+        assert_eq!(err.to_string(), "memtrace ends part way through record 0");
+    }
+}
@@ -0,0 +1,100 @@
+use std::sync::Mutex;
+
+/// number of accesses a miss stays outstanding for unless configured otherwise
+pub const DEFAULT_MSHR_LATENCY: u32 = 16;
+
+/// Miss status holding registers, each miss to a new line occupies one for `latency` accesses
+pub(crate) struct MshrFile {
+    count: usize,
+    latency: u32,
+    state: Mutex<MshrState>,
+}
+
+#[derive(Default)]
+struct MshrState {
+    /// (block, epoch the fill completes at)
+    in_flight: Vec<(u64, u32)>,
+    max_concurrency: usize,
+    stalls: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MshrStats {
+    pub count: usize,
+    /// the most misses that were outstanding at once
+    pub max_concurrency: usize,
+    /// misses that had to wait because every register was busy
+    pub stalls: u64,
+}
+
+impl MshrFile {
+    pub fn new(count: usize, latency: u32) -> MshrFile {
+        assert!(count > 0, "at least one mshr is required");
+        MshrFile {
+            count,
+            latency,
+            state: Mutex::new(MshrState::default()),
+        }
+    }
+
+    pub fn record_miss(&self, block: u64, epoch: u32) {
+        let mut state = self.state.lock().unwrap();
+        // the epoch wraps, so fills are compared by how far ahead of it they complete
+        state
+            .in_flight
+            .retain(|&(_, done)| (done.wrapping_sub(epoch) as i32) > 0);
+
+        // secondary miss to a line already being fetched shares its register
+        if state.in_flight.iter().any(|&(b, _)| b == block) {
+            return;
+        }
+
+        if state.in_flight.len() == self.count {
+            // wait for the earliest fill to complete and take over its register
+            state.stalls += 1;
+            let (earliest, _) = state
+                .in_flight
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (_, done))| done.wrapping_sub(epoch))
+                .unwrap();
+            let freed_at = state.in_flight.swap_remove(earliest).1;
+            state
+                .in_flight
+                .push((block, freed_at.wrapping_add(self.latency)));
+        } else {
+            state
+                .in_flight
+                .push((block, epoch.wrapping_add(self.latency)));
+        }
+
+        state.max_concurrency = state.max_concurrency.max(state.in_flight.len());
+    }
+
+    pub fn stats(&self) -> MshrStats {
+        let state = self.state.lock().unwrap();
+        MshrStats {
+            count: self.count,
+            max_concurrency: state.max_concurrency,
+            stalls: state.stalls,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wrapped_epoch() {
+        let mshrs = MshrFile::new(1, 4);
+        let start = u32::MAX - 1;
+        mshrs.record_miss(0, start);
+        // still in flight once the epoch has wrapped
+        mshrs.record_miss(1, start.wrapping_add(2));
+        mshrs.record_miss(2, start.wrapping_add(20));
+        let stats = mshrs.stats();
+        assert_eq!(stats.stalls, 1);
+        assert_eq!(stats.max_concurrency, 1);
+    }
+}
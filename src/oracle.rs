@@ -0,0 +1,55 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+};
+
+/// A fully associative LRU cache run alongside a real one, to separate conflict misses out
+pub(crate) struct FaOracle {
+    lines: usize,
+    state: Mutex<OracleState>,
+}
+
+#[derive(Default)]
+struct OracleState {
+    /// block to the time it was last used
+    last_used: HashMap<u64, u64>,
+    /// time last used to block, oldest first
+    by_age: BTreeMap<u64, u64>,
+    time: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl FaOracle {
+    pub fn new(lines: usize) -> FaOracle {
+        FaOracle {
+            lines,
+            state: Mutex::new(OracleState::default()),
+        }
+    }
+
+    /// `block` is any address that identifies the block, e.g. the address shifted by the block size
+    pub fn access(&self, block: u64) {
+        let mut state = self.state.lock().unwrap();
+        let time = state.time;
+        state.time += 1;
+
+        if let Some(last) = state.last_used.insert(block, time) {
+            state.by_age.remove(&last);
+            state.hits += 1;
+        } else {
+            state.misses += 1;
+            if state.last_used.len() > self.lines {
+                let (_, oldest) = state.by_age.pop_first().unwrap();
+                state.last_used.remove(&oldest);
+            }
+        }
+        state.by_age.insert(time, block);
+    }
+
+    /// hits and misses so far
+    pub fn stats(&self) -> (u64, u64) {
+        let state = self.state.lock().unwrap();
+        (state.hits, state.misses)
+    }
+}
@@ -0,0 +1,90 @@
+//! Deterministic address streams for common access patterns, to feed into
+//! [`CacheState::replay`](crate::thrashe::CacheState::replay) or
+//! [`CacheState::touch_iter`](crate::thrashe::CacheState::touch_iter)
+
+/// size of each node visited by [`pointer_chase`]
+pub const NODE_SIZE: u64 = 64;
+
+/// `count` consecutive elements of `stride` bytes starting at `base`
+pub fn sequential(base: u64, stride: u64, count: usize) -> impl Iterator<Item = u64> {
+    (0..count as u64).map(move |i| base + i * stride)
+}
+
+/// like [`sequential`] but `stride` may be negative, walking down from `base`
+pub fn strided(base: u64, stride: i64, count: usize) -> impl Iterator<Item = u64> {
+    (0..count as i64).map(move |i| base.wrapping_add_signed(i * stride))
+}
+
+/// `count` addresses drawn uniformly from `base..base + span`
+pub fn random_uniform(base: u64, span: u64, count: usize, seed: u64) -> impl Iterator<Item = u64> {
+    let mut rng = SplitMix64(seed);
+    (0..count).map(move |_| base + rng.next() % span.max(1))
+}
+
+/// walks a linked list of `count` [`NODE_SIZE`] byte nodes from `base`, visiting every node once
+/// in an order shuffled by `seed`, so each address depends on the one before it
+pub fn pointer_chase(base: u64, count: usize, seed: u64) -> impl Iterator<Item = u64> {
+    // sattolo's algorithm gives a single cycle through every node
+    let mut next: Vec<usize> = (0..count).collect();
+    let mut rng = SplitMix64(seed);
+    for i in (1..count).rev() {
+        let j = (rng.next() % i as u64) as usize;
+        next.swap(i, j);
+    }
+
+    let mut node = 0;
+    (0..count).map(move |_| {
+        let address = base + node as u64 * NODE_SIZE;
+        node = next[node];
+        address
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SplitMix64(pub u64);
+
+impl SplitMix64 {
+    pub fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thrashe::{CacheSpec, CacheState};
+
+    #[test]
+    fn sequential_matches_linear_access() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        let outcome = cache.touch_iter(sequential(4200, 8, 128));
+        assert_eq!(outcome.hits, 95);
+        assert_eq!(outcome.misses, 33);
+
+        let down: Vec<u64> = strided(4200, -8, 3).collect();
+        assert_eq!(down, [4200, 4192, 4184]);
+    }
+
+    #[test]
+    fn seeded_patterns() {
+        let random: Vec<u64> = random_uniform(4096, 1024, 100, 7).collect();
+        assert!(random.iter().all(|a| (4096..4096 + 1024).contains(a)));
+        assert_eq!(
+            random,
+            random_uniform(4096, 1024, 100, 7).collect::<Vec<_>>()
+        );
+        assert_ne!(
+            random,
+            random_uniform(4096, 1024, 100, 8).collect::<Vec<_>>()
+        );
+
+        let mut chase: Vec<u64> = pointer_chase(0, 50, 3).collect();
+        assert_eq!(chase[0], 0);
+        chase.sort_unstable();
+        assert_eq!(chase, sequential(0, NODE_SIZE, 50).collect::<Vec<_>>());
+    }
+}
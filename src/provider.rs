@@ -1,59 +1,314 @@
-use std::sync::RwLock;
+use std::{
+    any::{TypeId, type_name},
+    cell::Cell,
+    collections::BTreeMap,
+    mem, ptr,
+    sync::{
+        Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+        atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering},
+    },
+};
 
-use crate::thrashe::{CacheSpec, CacheState, Thrashe, ThrasheReport};
+use crate::thrashe::{AddressSource, BatchOutcome, CacheSpec, CacheState, Thrashe, ThrasheReport};
 
-// #[cfg(feature = "convenience_types")]
-// use paste::paste;
+#[cfg(feature = "convenience_types")]
+#[doc(hidden)]
+pub use paste::paste;
+
+/// What happens when a value is accessed while its provider has no cache configured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strictness {
+    /// the access is silently not recorded
+    Off,
+    /// print a warning on the first such access
+    Warn,
+    Panic,
+}
+
+/// A provider's [`Strictness`], see [`CacheProvider::strictness_state`]
+pub struct StrictnessState {
+    strictness: AtomicU8,
+    warned: AtomicBool,
+}
+
+impl StrictnessState {
+    pub const fn new() -> StrictnessState {
+        StrictnessState {
+            strictness: AtomicU8::new(Strictness::Off as u8),
+            warned: AtomicBool::new(false),
+        }
+    }
+
+    /// changing it re-arms the one time warning
+    pub fn set(&self, strictness: Strictness) {
+        self.strictness.store(strictness as u8, Ordering::Relaxed);
+        self.warned.store(false, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Strictness {
+        match self.strictness.load(Ordering::Relaxed) {
+            0 => Strictness::Off,
+            1 => Strictness::Warn,
+            _ => Strictness::Panic,
+        }
+    }
+}
 
-pub trait CacheProvider {
+impl Default for StrictnessState {
+    fn default() -> Self {
+        StrictnessState::new()
+    }
+}
+
+fn unconfigured_access<C: ?Sized + CacheProvider>() {
+    let state = C::strictness_state();
+    match state.get() {
+        Strictness::Off => {}
+        Strictness::Warn => {
+            if !state.warned.swap(true, Ordering::Relaxed) {
+                eprintln!(
+                    "thrashe: access through {} while no cache is configured, call configure first",
+                    type_name::<C>()
+                );
+            }
+        }
+        Strictness::Panic => panic!(
+            "thrashe: access through {} while no cache is configured",
+            type_name::<C>()
+        ),
+    }
+}
+
+pub trait CacheProvider: 'static {
     fn get_cache() -> &'static RwLock<Option<CacheState>>;
 
+    /// read access to the state, a panic while the lock was held doesn't stop further measurement
+    fn read_cache() -> RwLockReadGuard<'static, Option<CacheState>> {
+        Self::get_cache()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// write access to the state, a panic while the lock was held doesn't stop further measurement
+    fn write_cache() -> RwLockWriteGuard<'static, Option<CacheState>> {
+        Self::get_cache()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// runs `f` against the configured state, this is what every recorded access goes through
+    fn with_cache<R>(f: impl FnOnce(&CacheState) -> R) -> Option<R> {
+        let result = Self::read_cache().as_ref().map(f);
+        if result.is_none() {
+            unconfigured_access::<Self>();
+        }
+        result
+    }
+
+    /// set while a cache is configured, so [`CacheProvider::is_configured`] doesn't need the lock
+    ///
+    /// `None` makes it check under the lock instead. Only `configure` and `finish` keep it up to
+    /// date, not writes through [`CacheProvider::write_cache`]
+    fn configured_flag() -> Option<&'static AtomicBool> {
+        None
+    }
+
+    /// where this provider keeps its [`Strictness`]
+    ///
+    /// providers that don't override it get one of their own the first time it's needed, looked
+    /// up by type since a static here would be shared by all of them
+    fn strictness_state() -> &'static StrictnessState {
+        static STATES: Mutex<BTreeMap<TypeId, &'static StrictnessState>> =
+            Mutex::new(BTreeMap::new());
+        STATES
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(TypeId::of::<Self>())
+            .or_insert_with(|| Box::leak(Box::default()))
+    }
+
+    /// what happens when a value is accessed while this provider has no cache configured, off
+    /// unless set
+    fn set_strictness(strictness: Strictness) {
+        Self::strictness_state().set(strictness);
+    }
+
+    fn strictness() -> Strictness {
+        Self::strictness_state().get()
+    }
+
+    /// whether a cache is configured, for code that only instruments when one is
+    fn is_configured() -> bool {
+        match Self::configured_flag() {
+            Some(flag) => flag.load(Ordering::Relaxed),
+            None => Self::read_cache().is_some(),
+        }
+    }
+
     fn configure(spec: CacheSpec) -> Option<ThrasheReport> {
-        let state = Self::get_cache();
+        let mut state = Self::write_cache();
+        if let Some(flag) = Self::configured_flag() {
+            flag.store(true, Ordering::Relaxed);
+        }
         state
-            .write()
-            .unwrap()
             .replace(CacheState::from_spec(spec))
             .map(|s| s.make_report())
     }
 
+    /// [`CacheProvider::configure`] with a spec that might fail to build, which leaves the
+    /// provider as it was
+    fn configure_with<E>(
+        f: impl FnOnce() -> Result<CacheSpec, E>,
+    ) -> Result<Option<ThrasheReport>, E> {
+        Ok(Self::configure(f()?))
+    }
+
+    /// touches the blocks spanned by `field`, which should be a field of `base`, for studying
+    /// which fields of a struct are hot without wrapping each of them
+    fn touch_field<T, U>(base: &T, field: &U) -> Option<BatchOutcome> {
+        let start = base as *const T as u64;
+        let address = field as *const U as u64;
+        debug_assert!(
+            start <= address && address + size_of::<U>() as u64 <= start + size_of::<T>() as u64,
+            "field isn't part of base"
+        );
+        Self::with_cache(|state| state.touch_range(address, size_of::<U>() as u64))
+    }
+
+    /// starts capturing the configured cache's accesses, see [`CacheState::enable_capture`]
+    ///
+    /// returns whether a cache was configured to capture
+    fn enable_capture() -> bool {
+        Self::write_cache()
+            .as_mut()
+            .map(|state| state.enable_capture())
+            .is_some()
+    }
+
+    /// the addresses accessed since [`CacheProvider::enable_capture`] or the last take, ready to
+    /// replay
+    fn take_capture() -> Option<Vec<u64>> {
+        Self::read_cache().as_ref()?.take_capture()
+    }
+
     fn get_report() -> Option<ThrasheReport> {
-        let state = Self::get_cache();
-        state.read().unwrap().as_ref().map(|s| s.make_report())
+        Self::read_cache().as_ref().map(|s| s.make_report())
     }
 
     fn finish() -> Option<ThrasheReport> {
-        let state = Self::get_cache();
-        state.write().unwrap().take().map(|s| s.make_report())
+        let mut state = Self::write_cache();
+        if let Some(flag) = Self::configured_flag() {
+            flag.store(false, Ordering::Relaxed);
+        }
+        state.take().map(|s| s.make_report())
     }
 }
 
+/// Compile time proof that `C` is sound to record through from any thread: the state it hands
+/// out is `Sync`, and a [`Thrashe`] naming it is `Send`/`Sync` whenever its value and address
+/// source are, as it never holds a `C`
+///
+/// [`new_provider!`] checks every provider it declares with this. A provider can't get around it
+/// by keeping its state per thread, as state borrowed from a thread doesn't live long enough
+///
+/// ```compile_fail
+/// use std::sync::RwLock;
+/// use thrashe::{provider::CacheProvider, thrashe::CacheState};
+///
+/// enum PerThread {}
+///
+/// impl CacheProvider for PerThread {
+///     fn get_cache() -> &'static RwLock<Option<CacheState>> {
+///         thread_local! {
+///             static STATE: RwLock<Option<CacheState>> = const { RwLock::new(None) };
+///         }
+///         STATE.with(|state| state)
+///     }
+/// }
+/// ```
+pub const fn assert_provider<C: CacheProvider>() {
+    const fn send_sync<X: ?Sized + Send + Sync>() {}
+    const fn send<X: Send>() {}
+    const fn sync<X: Sync>() {}
+    const fn with_value<T: Send + Sync, C: CacheProvider, A: AddressSource + Send + Sync>() {
+        send::<Thrashe<T, C, A>>();
+        sync::<Thrashe<T, C, A>>();
+    }
+    send_sync::<RwLock<Option<CacheState>>>();
+    with_value::<u64, C, crate::thrashe::RealAddress>();
+    with_value::<u64, C, crate::thrashe::VirtualAddress>();
+}
+
+const _: () = {
+    assert_provider::<GlobalCache>();
+    assert_provider::<SamplingCache>();
+    assert_provider::<ThreadLocalCache>();
+    assert_provider::<StaticProvider<0>>();
+};
+
 #[macro_export]
 macro_rules! new_provider {
     ($name: ident) => {
         pub enum $name {}
 
-        new_type($name);
+        $crate::new_type!($name);
 
-        impl CacheProvider for $name {
-            fn get_cache() -> &'static RwLock<Option<CacheState>> {
-                static STATE: RwLock<Option<CacheState>> = RwLock::new(None);
+        const _: () = $crate::provider::assert_provider::<$name>();
+
+        impl $crate::provider::CacheProvider for $name {
+            fn get_cache() -> &'static ::std::sync::RwLock<Option<$crate::thrashe::CacheState>> {
+                static STATE: ::std::sync::RwLock<Option<$crate::thrashe::CacheState>> =
+                    ::std::sync::RwLock::new(None);
                 &STATE
             }
+
+            fn configured_flag() -> Option<&'static ::std::sync::atomic::AtomicBool> {
+                static CONFIGURED: ::std::sync::atomic::AtomicBool =
+                    ::std::sync::atomic::AtomicBool::new(false);
+                Some(&CONFIGURED)
+            }
+
+            fn strictness_state() -> &'static $crate::provider::StrictnessState {
+                static STRICTNESS: $crate::provider::StrictnessState =
+                    $crate::provider::StrictnessState::new();
+                &STRICTNESS
+            }
         }
     };
 }
 
+/// Runs `warm` to bring the cache into a steady state, then resets the counters and runs
+/// `measure`, returning the report of just the measured part
+///
+/// records into [`GlobalCache`] unless another provider is given with `cache: MyCache,` first
+#[macro_export]
+macro_rules! warm_then_measure {
+    (warm: $warm: block, measure: $measure: block $(,)?) => {
+        $crate::warm_then_measure!(cache: $crate::provider::GlobalCache, warm: $warm, measure: $measure)
+    };
+    (cache: $cache: ty, warm: $warm: block, measure: $measure: block $(,)?) => {{
+        $warm
+        <$cache as $crate::provider::CacheProvider>::with_cache(|state| state.reset_stats());
+        $measure
+        <$cache as $crate::provider::CacheProvider>::get_report()
+    }};
+}
+
 #[cfg(feature = "convenience_types")]
+#[doc(hidden)]
+#[macro_export]
 macro_rules! new_type {
     ($name: ident) => {
-        paste! {
-            pub type [<Thrashe $name>]<T> = Thrashe<T, $name>;
+        $crate::provider::paste! {
+            #[allow(dead_code)]
+            pub type [<Thrashe $name>]<T> = $crate::Thrashe<T, $name>;
         }
     };
 }
 
 #[cfg(not(feature = "convenience_types"))]
+#[doc(hidden)]
+#[macro_export]
 macro_rules! new_type {
     ($name: ident) => {};
 }
@@ -74,4 +329,560 @@ impl CacheProvider for GlobalCache {
         static STATE: RwLock<Option<CacheState>> = RwLock::new(None);
         &STATE
     }
+
+    fn strictness_state() -> &'static StrictnessState {
+        static STRICTNESS: StrictnessState = StrictnessState::new();
+        &STRICTNESS
+    }
+
+    fn configured_flag() -> Option<&'static AtomicBool> {
+        static CONFIGURED: AtomicBool = AtomicBool::new(false);
+        Some(&CONFIGURED)
+    }
+}
+
+/// Lossy sampling provider for instrumenting async code, accesses never wait for the lock.
+///
+/// Touching the state only needs shared access, so the lock is only contended while something
+/// holds it for writing, like `configure`/`finish` or [`CacheProvider::write_cache`].
+///
+/// An access made in that window is dropped rather than blocking the executor, so this records
+/// a sample of the accesses and can report fewer than [`GlobalCache`] would for the same run.
+/// The dropped accesses are counted, in [`ThrasheReport::skipped_accesses`] of its reports and
+/// [`SamplingCache::skipped_accesses`].
+pub enum SamplingCache {}
+
+impl SamplingCache {
+    fn skipped() -> &'static AtomicU64 {
+        static SKIPPED: AtomicU64 = AtomicU64::new(0);
+        &SKIPPED
+    }
+
+    /// accesses that weren't recorded because the state was locked for writing, since the last
+    /// `configure`
+    pub fn skipped_accesses() -> u64 {
+        Self::skipped().load(Ordering::Relaxed)
+    }
+}
+
+impl CacheProvider for SamplingCache {
+    fn get_cache() -> &'static RwLock<Option<CacheState>> {
+        static STATE: RwLock<Option<CacheState>> = RwLock::new(None);
+        &STATE
+    }
+
+    fn strictness_state() -> &'static StrictnessState {
+        static STRICTNESS: StrictnessState = StrictnessState::new();
+        &STRICTNESS
+    }
+
+    fn configured_flag() -> Option<&'static AtomicBool> {
+        static CONFIGURED: AtomicBool = AtomicBool::new(false);
+        Some(&CONFIGURED)
+    }
+
+    fn configure(spec: CacheSpec) -> Option<ThrasheReport> {
+        let mut state = Self::write_cache();
+        Self::configured_flag()
+            .unwrap()
+            .store(true, Ordering::Relaxed);
+        let skipped = Self::skipped().swap(0, Ordering::Relaxed);
+        state
+            .replace(CacheState::from_spec(spec))
+            .map(|s| s.make_report().with_skipped_accesses(skipped))
+    }
+
+    fn get_report() -> Option<ThrasheReport> {
+        let report = Self::read_cache().as_ref().map(|s| s.make_report())?;
+        Some(report.with_skipped_accesses(Self::skipped_accesses()))
+    }
+
+    fn finish() -> Option<ThrasheReport> {
+        let mut state = Self::write_cache();
+        Self::configured_flag()
+            .unwrap()
+            .store(false, Ordering::Relaxed);
+        let report = state.take().map(|s| s.make_report())?;
+        Some(report.with_skipped_accesses(Self::skipped_accesses()))
+    }
+
+    fn with_cache<R>(f: impl FnOnce(&CacheState) -> R) -> Option<R> {
+        let result = match Self::get_cache().try_read() {
+            Ok(guard) => guard.as_ref().map(f),
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner().as_ref().map(f),
+            Err(TryLockError::WouldBlock) => {
+                Self::skipped().fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+        if result.is_none() {
+            unconfigured_access::<Self>();
+        }
+        result
+    }
+}
+
+/// Provider giving every thread its own cache, so threads never contend over a lock or each
+/// other's lines
+///
+/// `configure`, `get_report` and `finish` act on the calling thread's cache, use
+/// [`ThreadLocalCache::finish_all`] to collect every thread's results at the end of a run
+///
+/// a thread's state is allocated by its first `configure` and kept for the rest of the process,
+/// so `finish_all` can still reach it once the thread has ended. Threads that never configure,
+/// e.g. ones that only ask for a report, don't allocate anything, and a thread pool only ever
+/// allocates one state per thread however many runs it does
+///
+/// it has no [`CacheProvider::configured_flag`], one flag can't tell threads apart, so
+/// `is_configured` reads the calling thread's state under its lock rather than being a single
+/// relaxed load
+pub enum ThreadLocalCache {}
+
+type ThreadState = &'static RwLock<Option<CacheState>>;
+
+thread_local! {
+    /// the calling thread's state, once it has configured one
+    static THREAD_STATE: Cell<Option<ThreadState>> = const { Cell::new(None) };
+}
+
+impl ThreadLocalCache {
+    /// the states configured and not yet finished
+    fn registry() -> &'static Mutex<Vec<ThreadState>> {
+        static REGISTRY: Mutex<Vec<ThreadState>> = Mutex::new(Vec::new());
+        &REGISTRY
+    }
+
+    /// finishes every thread's cache, merging them into one report with [`ThrasheReport::merge`]
+    ///
+    /// that's every thread that configured one and hasn't finished it, including threads that are
+    /// still running, `None` if none did. Panics if threads were configured with different specs
+    pub fn finish_all() -> Option<ThrasheReport> {
+        let registry = mem::take(
+            &mut *Self::registry()
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        registry
+            .iter()
+            .filter_map(|state| {
+                let state = state.write().unwrap_or_else(PoisonError::into_inner).take();
+                state.map(|s| s.make_report())
+            })
+            .reduce(|total, report| total.merge(&report))
+    }
+}
+
+impl CacheProvider for ThreadLocalCache {
+    /// shared by every thread
+    fn strictness_state() -> &'static StrictnessState {
+        static STRICTNESS: StrictnessState = StrictnessState::new();
+        &STRICTNESS
+    }
+
+    /// the calling thread's state, or one that's always empty if it hasn't configured one
+    fn get_cache() -> &'static RwLock<Option<CacheState>> {
+        static UNCONFIGURED: RwLock<Option<CacheState>> = RwLock::new(None);
+        THREAD_STATE.with(Cell::get).unwrap_or(&UNCONFIGURED)
+    }
+
+    fn configure(spec: CacheSpec) -> Option<ThrasheReport> {
+        let state = THREAD_STATE.with(|slot| {
+            slot.get().unwrap_or_else(|| {
+                // leaked so it outlives the thread for finish_all
+                let state = Box::leak(Box::new(RwLock::new(None)));
+                slot.set(Some(state));
+                state
+            })
+        });
+        {
+            let mut registry = Self::registry()
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if !registry.iter().any(|&other| ptr::eq(other, state)) {
+                registry.push(state);
+            }
+        }
+        state
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .replace(CacheState::from_spec(spec))
+            .map(|s| s.make_report())
+    }
+
+    fn finish() -> Option<ThrasheReport> {
+        let state = Self::get_cache();
+        Self::registry()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|&other| !ptr::eq(other, state));
+        let report = state.write().unwrap_or_else(PoisonError::into_inner).take();
+        report.map(|s| s.make_report())
+    }
+}
+
+/// how many distinct [`StaticProvider`]s there are
+pub const STATIC_PROVIDERS: usize = 16;
+
+static STATIC_STATES: [RwLock<Option<CacheState>>; STATIC_PROVIDERS] =
+    [const { RwLock::new(None) }; STATIC_PROVIDERS];
+static STATIC_CONFIGURED: [AtomicBool; STATIC_PROVIDERS] =
+    [const { AtomicBool::new(false) }; STATIC_PROVIDERS];
+static STATIC_STRICTNESS: [StrictnessState; STATIC_PROVIDERS] =
+    [const { StrictnessState::new() }; STATIC_PROVIDERS];
+
+/// Independent providers numbered by `ID`, for generic code or when declaring one with
+/// [`new_provider!`] isn't convenient, e.g. `Thrashe::<_, StaticProvider<3>>::new_in(value)`
+///
+/// `ID` must be below [`STATIC_PROVIDERS`], anything else fails to compile
+///
+/// ```compile_fail
+/// use thrashe::provider::{CacheProvider, StaticProvider};
+///
+/// StaticProvider::<{ thrashe::provider::STATIC_PROVIDERS }>::is_configured();
+/// ```
+pub enum StaticProvider<const ID: usize> {}
+
+impl<const ID: usize> StaticProvider<ID> {
+    /// `ID` checked against the statics, every lookup goes through this so none can index out of
+    /// bounds at run time
+    const INDEX: usize = {
+        assert!(ID < STATIC_PROVIDERS, "no static provider with this id");
+        ID
+    };
+}
+
+impl<const ID: usize> CacheProvider for StaticProvider<ID> {
+    fn get_cache() -> &'static RwLock<Option<CacheState>> {
+        &STATIC_STATES[Self::INDEX]
+    }
+
+    fn configured_flag() -> Option<&'static AtomicBool> {
+        Some(&STATIC_CONFIGURED[Self::INDEX])
+    }
+
+    fn strictness_state() -> &'static StrictnessState {
+        &STATIC_STRICTNESS[Self::INDEX]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Wake, Waker},
+        thread::{self, Thread},
+    };
+
+    use super::*;
+    use crate::Thrashe;
+
+    new_provider!(PoisonedCache);
+    new_provider!(FlaggedCache);
+    new_provider!(CaptureCache);
+
+    #[test]
+    fn captured_replay() {
+        assert!(!CaptureCache::enable_capture());
+        CaptureCache::configure(CacheSpec::spec_8kib_32bit_2way());
+        assert!(CaptureCache::enable_capture());
+        let foo = Thrashe::<_, CaptureCache>::new_in(42);
+        let bar = Thrashe::<_, CaptureCache, _>::with_address_in(7, 4096);
+        let _ = *foo;
+        let _ = *foo;
+        let _ = *bar;
+
+        let capture = CaptureCache::take_capture().unwrap();
+        assert_eq!(capture.len(), 3);
+        assert_eq!(capture[2], 4096);
+        let report = CaptureCache::finish().unwrap();
+
+        let replayed = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        replayed.replay(&capture);
+        assert_eq!(replayed.make_report(), report);
+    }
+
+    /// taken by every test using [`ThreadLocalCache`], as `finish_all` reaches into every thread
+    static THREAD_LOCAL: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn configured_flag() {
+        assert!(!FlaggedCache::is_configured());
+        FlaggedCache::configure(CacheSpec::spec_8kib_32bit_2way());
+        assert!(FlaggedCache::is_configured());
+        FlaggedCache::finish();
+        assert!(!FlaggedCache::is_configured());
+
+        assert!(!StaticProvider::<4>::is_configured());
+        StaticProvider::<4>::configure(CacheSpec::spec_8kib_32bit_2way());
+        assert!(StaticProvider::<4>::is_configured());
+        StaticProvider::<4>::finish();
+        assert!(!StaticProvider::<4>::is_configured());
+    }
+
+    #[test]
+    fn static_providers() {
+        StaticProvider::<1>::configure(CacheSpec::spec_8kib_32bit_2way());
+        StaticProvider::<2>::configure(CacheSpec::new(5, 0, 0));
+        let a = Thrashe::<_, StaticProvider<1>>::new_in(1u64);
+        let b = Thrashe::<_, StaticProvider<2>>::new_in(2u64);
+        for _ in 0..3 {
+            assert_eq!(*a + *b, 3);
+        }
+        assert!(!StaticProvider::<3>::is_configured());
+
+        let report_a = StaticProvider::<1>::finish().unwrap();
+        assert_eq!(report_a.access_count(), 3);
+        assert_eq!(report_a.spec(), &CacheSpec::spec_8kib_32bit_2way());
+        assert!(StaticProvider::<2>::is_configured());
+        assert_eq!(StaticProvider::<2>::finish().unwrap().access_count(), 3);
+    }
+
+    #[test]
+    fn recovers_from_poison() {
+        PoisonedCache::configure(CacheSpec::spec_8kib_32bit_2way());
+
+        let _ = thread::spawn(|| {
+            let _guard = PoisonedCache::get_cache().write().unwrap();
+            panic!("poisoning the provider lock");
+        })
+        .join();
+        assert!(PoisonedCache::get_cache().is_poisoned());
+
+        PoisonedCache::read_cache()
+            .as_ref()
+            .unwrap()
+            .touch_address(0);
+
+        let report = PoisonedCache::get_report().unwrap();
+        assert_eq!(report.access_count(), 1);
+        assert!(PoisonedCache::finish().is_some());
+    }
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    /// gives the executor a turn, like `tokio::task::yield_now`
+    async fn yield_now() {
+        let mut yielded = false;
+        std::future::poll_fn(|cx| {
+            if yielded {
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    #[test]
+    fn sampling_in_async() {
+        SamplingCache::configure(CacheSpec::spec_8kib_32bit_2way());
+
+        let values: Vec<Thrashe<u64, SamplingCache>> = (0..4).map(Thrashe::new_in).collect();
+        let sum = block_on(async {
+            let mut sum = 0;
+            for value in &values {
+                sum += **value;
+                yield_now().await;
+                sum += **value;
+            }
+            sum
+        });
+        assert_eq!(sum, 12);
+
+        assert_eq!(
+            SamplingCache::get_report().unwrap().skipped_accesses(),
+            Some(0)
+        );
+
+        // a value read while the state is locked for writing isn't recorded, but is counted
+        let guard = SamplingCache::write_cache();
+        assert_eq!(*values[0], 0);
+        drop(guard);
+
+        let report = SamplingCache::finish().unwrap();
+        assert_eq!(report.access_count(), 8);
+        assert_eq!(report.skipped_accesses(), Some(1));
+        assert_eq!(SamplingCache::skipped_accesses(), 1);
+        assert_eq!(
+            CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way())
+                .make_report()
+                .skipped_accesses(),
+            None
+        );
+    }
+
+    new_provider!(ForgottenCache);
+
+    #[test]
+    fn per_thread_reports() {
+        let _guard = THREAD_LOCAL.lock().unwrap_or_else(PoisonError::into_inner);
+        let reports: Vec<ThrasheReport> = thread::scope(|s| {
+            let handles: Vec<_> = (0..4u64)
+                .map(|t| {
+                    s.spawn(move || {
+                        ThreadLocalCache::configure(CacheSpec::spec_8kib_32bit_2way());
+                        for i in 0..=t * 8 {
+                            ThreadLocalCache::with_cache(|state| state.touch_address(4 * i));
+                        }
+                        ThreadLocalCache::get_report().unwrap()
+                    })
+                })
+                .collect();
+            // a thread that only looks never gets a cache of its own
+            s.spawn(|| assert!(ThreadLocalCache::get_report().is_none()));
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(ThreadLocalCache::registry().lock().unwrap().len(), 4);
+        let merged = ThreadLocalCache::finish_all().unwrap();
+        assert!(ThreadLocalCache::registry().lock().unwrap().is_empty());
+        assert_eq!(
+            merged.access_count(),
+            reports.iter().map(|r| r.access_count()).sum::<u64>()
+        );
+        assert_eq!(merged.hits(), reports.iter().map(|r| r.hits()).sum::<u64>());
+        assert_eq!(
+            merged.misses(),
+            reports.iter().map(|r| r.misses()).sum::<u64>()
+        );
+        assert_eq!(merged.access_count(), 1 + 9 + 17 + 25);
+        assert!(ThreadLocalCache::finish_all().is_none());
+    }
+
+    new_provider!(WarmCache);
+
+    #[test]
+    fn warm_up() {
+        WarmCache::configure(CacheSpec::spec_8kib_32bit_2way());
+        let values: Vec<_> = (0..64)
+            .map(|i| Thrashe::<_, WarmCache, _>::with_address_in(i, 32 * i))
+            .collect();
+        let report = crate::warm_then_measure! {
+            cache: WarmCache,
+            warm: {
+                for value in &values {
+                    let _ = **value;
+                }
+            },
+            measure: {
+                for value in values.iter().rev() {
+                    let _ = **value;
+                }
+            },
+        }
+        .unwrap();
+        assert_eq!(report.access_count(), 64);
+        assert_eq!(report.hits(), 64);
+        assert_eq!(report.occupancy(), 64);
+        WarmCache::finish();
+    }
+
+    new_provider!(FieldCache);
+
+    #[test]
+    fn hot_fields() {
+        #[repr(C, align(64))]
+        struct Node {
+            header: [u8; 64],
+            hot: u64,
+        }
+        let node = Node {
+            header: [0; 64],
+            hot: 0,
+        };
+
+        FieldCache::configure(CacheSpec::spec_8kib_32bit_2way());
+        let header = FieldCache::touch_field(&node, &node.header).unwrap();
+        assert_eq!(header.misses, 2);
+        for _ in 0..3 {
+            FieldCache::touch_field(&node, &node.hot);
+        }
+
+        // the hot field is in its own block, so only its first touch missed
+        let report = FieldCache::finish().unwrap();
+        assert_eq!(report.access_count(), 5);
+        assert_eq!(report.misses(), 3);
+    }
+
+    new_provider!(LazyCache);
+
+    #[test]
+    fn fallible_configure() {
+        let err = LazyCache::configure_with(|| Err::<CacheSpec, _>("bad spec"));
+        assert_eq!(err, Err("bad spec"));
+        assert!(LazyCache::read_cache().is_none());
+
+        let previous = LazyCache::configure_with(|| Ok::<_, ()>(CacheSpec::spec_8kib_32bit_2way()));
+        assert_eq!(previous, Ok(None));
+        assert!(LazyCache::finish().is_some());
+    }
+
+    #[test]
+    fn strict_warns_once() {
+        ForgottenCache::set_strictness(Strictness::Warn);
+        let value = Thrashe::<_, ForgottenCache>::new_in(1);
+        for _ in 0..3 {
+            let _ = *value;
+        }
+        let warned = &ForgottenCache::strictness_state().warned;
+        assert!(warned.load(Ordering::Relaxed));
+        assert!(ForgottenCache::finish().is_none());
+        // other providers keep their own
+        assert_eq!(FlaggedCache::strictness(), Strictness::Off);
+        ForgottenCache::set_strictness(Strictness::Off);
+
+        let _ = *value;
+        assert!(!warned.load(Ordering::Relaxed));
+    }
+
+    enum PlainA {}
+    enum PlainB {}
+
+    impl CacheProvider for PlainA {
+        fn get_cache() -> &'static RwLock<Option<CacheState>> {
+            static STATE: RwLock<Option<CacheState>> = RwLock::new(None);
+            &STATE
+        }
+    }
+
+    impl CacheProvider for PlainB {
+        fn get_cache() -> &'static RwLock<Option<CacheState>> {
+            static STATE: RwLock<Option<CacheState>> = RwLock::new(None);
+            &STATE
+        }
+    }
+
+    #[test]
+    fn default_strictness_per_provider() {
+        PlainA::set_strictness(Strictness::Panic);
+        assert_eq!(PlainA::strictness(), Strictness::Panic);
+        assert_eq!(PlainB::strictness(), Strictness::Off);
+        assert!(ptr::eq(
+            PlainA::strictness_state(),
+            PlainA::strictness_state()
+        ));
+        PlainA::set_strictness(Strictness::Off);
+    }
 }
@@ -0,0 +1,236 @@
+use std::{
+    cmp::Reverse,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use crate::patterns::SplitMix64;
+
+/// Where a newly filled block starts out in its set's recency order and which line makes way for
+/// it, the least recently used unless said otherwise
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplacementPolicy {
+    /// new blocks go in as most recently used
+    #[default]
+    Lru,
+    /// bimodal insertion, new blocks go in as least recently used except for one fill in every
+    /// [`BIP_THROTTLE`], so a scan can't flush out a working set
+    Bip,
+    /// dynamic insertion, a few leader sets always use LRU, a few always use BIP, and the rest
+    /// follow whichever of the two is missing less
+    Dip,
+    /// LRU insertion, but the victim is picked among the older half of a full set by a hash of the
+    /// incoming tag, so strides that defeat LRU don't always evict the block needed next.
+    /// Deterministic for a given `seed`
+    HashedLru { seed: u64 },
+}
+
+/// Which line is replaced when several are equally good victims, like the invalid lines left by a
+/// flush or lines BIP inserted with the same age
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    #[default]
+    LowestWay,
+    HighestWay,
+    /// a pseudo random one of them, the same sequence every run
+    Random,
+}
+
+/// Where newly filled blocks go in, overriding the [`ReplacementPolicy`]'s choice
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Insertion {
+    /// wherever the replacement policy puts them
+    #[default]
+    Policy,
+    /// bimodal insertion as least recently used, except that with `probability` a block goes in
+    /// as most recently used. Unlike [`ReplacementPolicy::Bip`] the exceptions are picked at
+    /// random instead of every so many fills, deterministic for a given `seed`
+    Bip { probability: f64, seed: u64 },
+}
+
+/// one in this many BIP fills is inserted as most recently used
+pub const BIP_THROTTLE: u32 = 32;
+
+/// every constituency of this many sets has one LRU and one BIP leader
+const CONSTITUENCY: usize = 32;
+/// saturation point of the policy selection counter
+const PSEL_MAX: u32 = 1023;
+
+enum Role {
+    LruLeader,
+    BipLeader,
+    Follower,
+}
+
+/// Per cache state needed to apply a [`ReplacementPolicy`]
+pub(crate) struct Replacement {
+    policy: ReplacementPolicy,
+    insertion: Insertion,
+    /// draws made for [`Insertion::Bip`]
+    insertion_draws: AtomicU64,
+    constituency: usize,
+    bip_fills: AtomicU32,
+    /// goes up on misses in LRU leaders and down on misses in BIP leaders
+    psel: AtomicU32,
+}
+
+impl Replacement {
+    pub fn new(policy: ReplacementPolicy, set_num: usize) -> Replacement {
+        Replacement {
+            policy,
+            insertion: Insertion::Policy,
+            insertion_draws: AtomicU64::new(0),
+            constituency: set_num.min(CONSTITUENCY),
+            bip_fills: AtomicU32::new(0),
+            psel: AtomicU32::new(PSEL_MAX / 2),
+        }
+    }
+
+    pub fn set_insertion(&mut self, insertion: Insertion) {
+        self.insertion = insertion;
+    }
+
+    fn role(&self, set: usize) -> Role {
+        match set % self.constituency {
+            0 => Role::LruLeader,
+            i if i == self.constituency - 1 => Role::BipLeader,
+            _ => Role::Follower,
+        }
+    }
+
+    /// whether a block filled into `set` goes in as most recently used
+    pub fn insert_mru(&self, set: usize) -> bool {
+        if let Insertion::Bip { probability, seed } = self.insertion {
+            let draw = self.insertion_draws.fetch_add(1, Ordering::Relaxed);
+            let hash = SplitMix64(seed.wrapping_add(draw)).next();
+            // the top 53 bits as a uniform float in [0, 1)
+            return ((hash >> 11) as f64 / (1u64 << 53) as f64) < probability;
+        }
+        let bimodal = match self.policy {
+            ReplacementPolicy::Lru | ReplacementPolicy::HashedLru { .. } => false,
+            ReplacementPolicy::Bip => true,
+            ReplacementPolicy::Dip => match self.role(set) {
+                Role::LruLeader => false,
+                Role::BipLeader => true,
+                Role::Follower => self.psel.load(Ordering::Relaxed) > PSEL_MAX / 2,
+            },
+        };
+        !bimodal
+            || self
+                .bip_fills
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(BIP_THROTTLE)
+    }
+
+    /// the way a block with `tag` replaces in a full set whose least recently used way is `lru`,
+    /// `ages` gives how long ago every way was used, larger for older ways
+    pub fn victim(&self, tag: u32, lru: usize, ages: impl FnOnce() -> Vec<u32>) -> usize {
+        let ReplacementPolicy::HashedLru { seed } = self.policy else {
+            return lru;
+        };
+        let ages = ages();
+        let mut ways: Vec<usize> = (0..ages.len()).collect();
+        ways.sort_unstable_by_key(|&way| Reverse(ages[way]));
+        let candidates = (ways.len() / 2).max(2).min(ways.len());
+        let hash = SplitMix64(seed ^ tag as u64).next();
+        ways[(hash % candidates as u64) as usize]
+    }
+
+    pub fn record_miss(&self, set: usize) {
+        if self.policy != ReplacementPolicy::Dip {
+            return;
+        }
+        let step = |psel: u32| match self.role(set) {
+            Role::LruLeader => (psel < PSEL_MAX).then(|| psel + 1),
+            Role::BipLeader => psel.checked_sub(1),
+            Role::Follower => None,
+        };
+        let _ = self
+            .psel
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, step);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::thrashe::{CacheSpec, CacheState};
+
+    #[test]
+    fn dueling_insertion() {
+        // working sets that shift every few laps favour LRU, whose fills are all kept
+        let lines = 2048;
+        let mut trace = Vec::new();
+        for window in 0..6u64 {
+            let blocks: Vec<u64> = (0..lines).map(|b| (window * lines + b) * 32).collect();
+            trace.extend(blocks.repeat(4));
+        }
+        // then a reused half of the cache between streams that are never touched again favours BIP,
+        // whose fills don't push the reused blocks out
+        let mut stream = 100_000;
+        for _ in 0..60 {
+            trace.extend((0..lines / 2).map(|b| (50_000 + b) * 32));
+            trace.extend((stream..stream + lines).map(|b| b * 32));
+            stream += lines;
+        }
+
+        let hits = |policy| {
+            let cache = CacheState::from_spec(CacheSpec::new(5, 10, 1).with_policy(policy));
+            cache.replay(&trace);
+            cache.make_report().hits()
+        };
+        let (lru, bip, dip) = (
+            hits(ReplacementPolicy::Lru),
+            hits(ReplacementPolicy::Bip),
+            hits(ReplacementPolicy::Dip),
+        );
+        assert!(dip > lru, "dip {dip} lru {lru}");
+        assert!(dip > bip, "dip {dip} bip {bip}");
+    }
+
+    #[test]
+    fn bimodal_insertion() {
+        // a reused half of the cache between streams that are never touched again
+        let lines = 2048;
+        let mut trace = Vec::new();
+        let mut stream = 100_000;
+        for _ in 0..40 {
+            trace.extend((0..lines / 2).map(|b| (50_000 + b) * 32));
+            trace.extend((stream..stream + lines).map(|b| b * 32));
+            stream += lines;
+        }
+
+        let hits = |insertion| {
+            let cache = CacheState::from_spec(CacheSpec::new(5, 10, 1)).with_insertion(insertion);
+            cache.replay(&trace);
+            cache.make_report().hits()
+        };
+        let bip = Insertion::Bip {
+            probability: 1.0 / 32.0,
+            seed: 3,
+        };
+        let (mru, bimodal) = (hits(Insertion::Policy), hits(bip));
+        // always inserting as most recently used lets every stream flush the reused blocks
+        assert_eq!(mru, 0);
+        // at least a quarter of the reused blocks survive each stream
+        assert!(bimodal > 39 * (lines / 2) / 4, "bip {bimodal}");
+        assert_eq!(hits(bip), bimodal);
+    }
+
+    #[test]
+    fn hashed_victims() {
+        // one more block than there are ways, LRU always evicts the block needed next
+        let spec = CacheSpec::new(5, 4, 2);
+        let trace = crate::analysis::conflict_set(&spec, 0, 5).repeat(100);
+        let misses = |policy| {
+            let cache = CacheState::from_spec(spec.clone().with_policy(policy));
+            cache.replay(&trace);
+            cache.make_report().misses()
+        };
+
+        let lru = misses(ReplacementPolicy::Lru);
+        assert_eq!(lru, 500);
+        let hashed = misses(ReplacementPolicy::HashedLru { seed: 7 });
+        assert!(hashed < lru * 9 / 10, "hashed {hashed} lru {lru}");
+        assert_eq!(misses(ReplacementPolicy::HashedLru { seed: 7 }), hashed);
+    }
+}
@@ -0,0 +1,96 @@
+use std::{collections::HashMap, sync::Mutex};
+
+/// Timestamps of the latest access and eviction of every block, fed by `touch_address_at`
+pub(crate) struct TemporalTracker {
+    near_miss_window: u64,
+    state: Mutex<TemporalState>,
+}
+
+#[derive(Default)]
+struct TemporalState {
+    last_access: HashMap<u64, u64>,
+    evicted_at: HashMap<u64, u64>,
+    reuses: u64,
+    total_inter_access: u64,
+    near_misses: Vec<NearMiss>,
+}
+
+/// A block that missed shortly after being evicted, a slightly larger cache would have kept it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NearMiss {
+    /// block aligned address
+    pub block: u64,
+    pub evicted_at: u64,
+    pub reused_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemporalStats {
+    /// accesses to a block that had been accessed before
+    pub reuses: u64,
+    /// mean time between consecutive accesses to the same block
+    pub mean_inter_access: f64,
+    pub near_misses: Vec<NearMiss>,
+}
+
+impl TemporalTracker {
+    pub fn new(near_miss_window: u64) -> TemporalTracker {
+        TemporalTracker {
+            near_miss_window,
+            state: Mutex::new(TemporalState::default()),
+        }
+    }
+
+    /// new blocks are left out once `max_blocks` are tracked, returns whether any were
+    pub fn record(
+        &self,
+        block: u64,
+        timestamp: u64,
+        hit: bool,
+        evicted: Option<u64>,
+        max_blocks: usize,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let mut dropped = false;
+        if state.last_access.len() >= max_blocks && !state.last_access.contains_key(&block) {
+            dropped = true;
+        } else if let Some(last) = state.last_access.insert(block, timestamp) {
+            state.reuses += 1;
+            state.total_inter_access += timestamp.saturating_sub(last);
+        }
+
+        if let Some(evicted_at) = state.evicted_at.remove(&block)
+            && !hit
+            && timestamp.saturating_sub(evicted_at) <= self.near_miss_window
+        {
+            state.near_misses.push(NearMiss {
+                block,
+                evicted_at,
+                reused_at: timestamp,
+            });
+        }
+
+        if let Some(evicted) = evicted {
+            if state.evicted_at.len() >= max_blocks && !state.evicted_at.contains_key(&evicted) {
+                dropped = true;
+            } else {
+                state.evicted_at.insert(evicted, timestamp);
+            }
+        }
+        dropped
+    }
+
+    pub fn stats(&self) -> TemporalStats {
+        let state = self.state.lock().unwrap();
+        let mean_inter_access = if state.reuses == 0 {
+            0.0
+        } else {
+            state.total_inter_access as f64 / state.reuses as f64
+        };
+        TemporalStats {
+            reuses: state.reuses,
+            mean_inter_access,
+            near_misses: state.near_misses.clone(),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Utilities for checking the simulator itself
+
+use std::{sync::RwLock, thread};
+
+use crate::thrashe::{CacheSpec, CacheState, ThrasheReport};
+
+/// Hammers one shared cache from `threads` threads, each touching `ops_per_thread` addresses
+/// produced by `addr_gen(thread, op)`, through the same lock a provider uses.
+///
+/// Panics if the final report isn't internally consistent, otherwise returns it.
+pub fn stress<F>(
+    spec: CacheSpec,
+    threads: usize,
+    ops_per_thread: usize,
+    addr_gen: F,
+) -> ThrasheReport
+where
+    F: Fn(usize, usize) -> u64 + Sync,
+{
+    let cache = RwLock::new(Some(CacheState::from_spec(spec)));
+
+    thread::scope(|s| {
+        for thread in 0..threads {
+            let cache = &cache;
+            let addr_gen = &addr_gen;
+            s.spawn(move || {
+                for op in 0..ops_per_thread {
+                    if let Some(state) = &*cache.read().unwrap() {
+                        state.touch_address(addr_gen(thread, op));
+                    }
+                }
+            });
+        }
+    });
+
+    let report = cache.into_inner().unwrap().unwrap().make_report();
+    assert_eq!(
+        report.access_count() as usize,
+        threads * ops_per_thread,
+        "lost accesses: {report:?}"
+    );
+    assert_eq!(
+        report.hits() + report.misses(),
+        report.access_count(),
+        "hits and misses don't add up: {report:?}"
+    );
+    assert!(
+        report.occupancy() <= report.spec().capacity_lines(),
+        "more valid lines than the cache holds: {report:?}"
+    );
+    report
+}
+
+/// Checks several expectations of a report at once, panicking with every mismatch rather than
+/// just the first
+///
+/// `ReportAssert::new(&report).hits(96).misses(32).min_hit_rate(0.7).check()`
+#[must_use = "nothing is checked until `check` is called"]
+pub struct ReportAssert<'a> {
+    report: &'a ThrasheReport,
+    failures: Vec<String>,
+}
+
+impl<'a> ReportAssert<'a> {
+    pub fn new(report: &'a ThrasheReport) -> ReportAssert<'a> {
+        ReportAssert {
+            report,
+            failures: Vec::new(),
+        }
+    }
+
+    fn expect<T: PartialEq + std::fmt::Debug>(
+        mut self,
+        what: &str,
+        expected: T,
+        actual: T,
+    ) -> Self {
+        if expected != actual {
+            self.failures
+                .push(format!("{what}: expected {expected:?}, got {actual:?}"));
+        }
+        self
+    }
+
+    pub fn access_count(self, expected: u64) -> Self {
+        let actual = self.report.access_count();
+        self.expect("access count", expected, actual)
+    }
+
+    pub fn hits(self, expected: u64) -> Self {
+        let actual = self.report.hits();
+        self.expect("hits", expected, actual)
+    }
+
+    pub fn misses(self, expected: u64) -> Self {
+        let actual = self.report.misses();
+        self.expect("misses", expected, actual)
+    }
+
+    pub fn occupancy(self, expected: usize) -> Self {
+        let actual = self.report.occupancy();
+        self.expect("occupancy", expected, actual)
+    }
+
+    pub fn min_hit_rate(mut self, min: f64) -> Self {
+        let actual = self.report.hit_rate();
+        if actual < min {
+            self.failures
+                .push(format!("hit rate: expected at least {min}, got {actual}"));
+        }
+        self
+    }
+
+    pub fn max_hit_rate(mut self, max: f64) -> Self {
+        let actual = self.report.hit_rate();
+        if actual > max {
+            self.failures
+                .push(format!("hit rate: expected at most {max}, got {actual}"));
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn check(self) {
+        if !self.failures.is_empty() {
+            panic!(
+                "report didn't match:\n  {}\n{:?}",
+                self.failures.join("\n  "),
+                self.report
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::patterns;
+
+    #[test]
+    fn eight_threads() {
+        // each thread walks its own region plus a region shared by everyone
+        let report = stress(
+            CacheSpec::spec_8kib_32bit_2way(),
+            8,
+            10_000,
+            |thread, op| {
+                let offset = (op % 256) as u64 * 8;
+                if op % 2 == 0 {
+                    offset
+                } else {
+                    (thread as u64 + 1) * 65536 + offset
+                }
+            },
+        );
+
+        assert_eq!(report.access_count(), 80_000);
+        assert_eq!(report.hits() + report.misses(), 80_000);
+        assert!(report.occupancy() <= report.spec().capacity_lines());
+        assert!(report.hits() > 0);
+    }
+
+    #[test]
+    fn report_assertions() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        cache.touch_iter(patterns::sequential(4200, 8, 128));
+        let report = cache.make_report();
+
+        ReportAssert::new(&report)
+            .access_count(128)
+            .hits(95)
+            .misses(33)
+            .min_hit_rate(0.7)
+            .max_hit_rate(0.75)
+            .check();
+
+        let failure = std::panic::catch_unwind(|| {
+            ReportAssert::new(&report)
+                .hits(96)
+                .misses(32)
+                .min_hit_rate(0.7)
+                .check()
+        })
+        .unwrap_err();
+        let message = failure.downcast_ref::<String>().unwrap();
+        assert!(message.contains("hits: expected 96, got 95"));
+        assert!(message.contains("misses: expected 32, got 33"));
+        assert!(!message.contains("hit rate"));
+    }
+}
@@ -1,65 +1,361 @@
 use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Write},
     marker::PhantomData,
     ops::Deref,
-    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
     vec,
 };
 
-use crate::provider::{CacheProvider, GlobalCache};
+use crate::{
+    analysis::{AnalysisBudget, ReuseDistanceSketch, ReuseTracker},
+    counter::Counter,
+    events::{CacheEvent, EventBuffer},
+    lifetime::{LifetimeHistogram, LifetimeTracker},
+    mshr::{DEFAULT_MSHR_LATENCY, MshrFile, MshrStats},
+    oracle::FaOracle,
+    patterns::SplitMix64,
+    provider::{CacheProvider, GlobalCache},
+    replacement::{Insertion, Replacement, ReplacementPolicy, TieBreak},
+    temporal::{TemporalStats, TemporalTracker},
+    timeline::{Timeline, TimelineRecorder},
+    write::{WritePolicy, Writebacks},
+};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CacheSpec {
     block_size_bits: u8,
     set_num_bits: u8,
-    lines_per_set_bits: u8,
+    lines_per_set: usize,
+    policy: ReplacementPolicy,
+    banks: usize,
+    ports: usize,
 }
 
 impl CacheSpec {
-    fn set_num(&self) -> usize {
+    /// cache with `2^block_size_bits` byte blocks, `2^set_num_bits` sets and `2^lines_per_set_bits` ways
+    pub const fn new(block_size_bits: u8, set_num_bits: u8, lines_per_set_bits: u8) -> CacheSpec {
+        CacheSpec::with_ways(block_size_bits, set_num_bits, 1 << lines_per_set_bits)
+    }
+
+    /// like [`CacheSpec::new`] but with any number of ways, e.g. 3 or 12
+    pub const fn with_ways(block_size_bits: u8, set_num_bits: u8, ways: usize) -> CacheSpec {
+        assert!(ways > 0, "a cache needs at least one way");
+        CacheSpec {
+            block_size_bits,
+            set_num_bits,
+            lines_per_set: ways,
+            policy: ReplacementPolicy::Lru,
+            banks: 1,
+            ports: 1,
+        }
+    }
+
+    pub const fn with_policy(mut self, policy: ReplacementPolicy) -> CacheSpec {
+        self.policy = policy;
+        self
+    }
+
+    pub const fn policy(&self) -> ReplacementPolicy {
+        self.policy
+    }
+
+    /// splits the sets into `banks` banks, set `i` is in bank `i % banks`
+    pub const fn with_banks(mut self, banks: usize) -> CacheSpec {
+        assert!(banks > 0, "a cache needs at least one bank");
+        self.banks = banks;
+        self
+    }
+
+    /// checks the spec can be virtually indexed and physically tagged with `2^page_bits` byte
+    /// pages: the index and offset bits have to fit in the page offset, which translation leaves
+    /// alone, so the lookup can start before the physical address is known
+    pub const fn require_vipt(self, page_bits: u8) -> Result<CacheSpec, ViptError> {
+        let index_bits = self.set_num_bits + self.block_size_bits;
+        if index_bits > page_bits {
+            return Err(ViptError {
+                index_bits,
+                page_bits,
+            });
+        }
+        Ok(self)
+    }
+
+    /// how many accesses the cache can take each cycle, see [`CacheState::touch_cycle`]
+    pub const fn with_ports(mut self, ports: usize) -> CacheSpec {
+        assert!(ports > 0, "a cache needs at least one port");
+        self.ports = ports;
+        self
+    }
+
+    pub const fn ports(&self) -> usize {
+        self.ports
+    }
+
+    pub const fn banks(&self) -> usize {
+        self.banks
+    }
+
+    /// the bank an address maps to
+    pub const fn bank_of(&self, address: u64) -> usize {
+        self.index_of(address) % self.banks
+    }
+
+    pub const fn set_num(&self) -> usize {
         1 << self.set_num_bits
     }
 
-    fn lines_per_set(&self) -> usize {
-        1 << self.lines_per_set_bits
+    pub const fn lines_per_set(&self) -> usize {
+        self.lines_per_set
     }
 
-    fn block_size(&self) -> usize {
+    /// ways per set, the same as [`CacheSpec::lines_per_set`]
+    pub const fn associativity(&self) -> usize {
+        self.lines_per_set()
+    }
+
+    /// block size in bytes
+    pub const fn block_size(&self) -> usize {
         1 << self.block_size_bits
     }
 
-    fn size(&self) -> u64 {
-        self.block_size() as u64 * self.set_num() as u64 * self.lines_per_set() as u64
+    pub const fn block_size_bits(&self) -> u8 {
+        self.block_size_bits
+    }
+
+    /// capacity in bytes
+    pub const fn size(&self) -> u64 {
+        self.block_size() as u64 * self.capacity_lines() as u64
+    }
+
+    /// total number of lines across all sets
+    pub const fn capacity_lines(&self) -> usize {
+        self.set_num() * self.lines_per_set()
+    }
+
+    /// distance in bytes between consecutive addresses that map to the same set
+    pub const fn conflict_stride(&self) -> u64 {
+        self.block_size() as u64 * self.set_num() as u64
     }
 
-    fn split(&self, address: u64) -> (u32, u32) {
-        let set_index = ((address >> self.block_size_bits) & (self.block_size() as u64 - 1)) as u32;
+    /// how an `address_bits` wide address splits into tag, set index and block offset
+    pub const fn bit_layout(&self, address_bits: u8) -> BitLayout {
+        let index_bits = self.set_num_bits;
+        let offset_bits = self.block_size_bits;
+        assert!(
+            address_bits >= index_bits + offset_bits,
+            "addresses are too narrow to index this cache"
+        );
+        BitLayout {
+            tag_bits: address_bits - index_bits - offset_bits,
+            index_bits,
+            offset_bits,
+        }
+    }
+
+    /// the set an address maps to
+    pub const fn index_of(&self, address: u64) -> usize {
+        self.split(address).0 as usize
+    }
+
+    const fn split(&self, address: u64) -> (u32, u32) {
+        let set_index = ((address >> self.block_size_bits) & (self.set_num() as u64 - 1)) as u32;
         // this will lose some information with 64 bit addresses, though usually only 40 something bits are used
         let tag = (address >> (self.block_size_bits + self.set_num_bits)) as u32;
         (set_index, tag)
     }
+
+    /// block aligned address of a block, the inverse of `split`
+    fn join(&self, set_index: u32, tag: u32) -> u64 {
+        ((tag as u64) << (self.block_size_bits + self.set_num_bits))
+            | ((set_index as u64) << self.block_size_bits)
+    }
 }
 
-impl CacheSpec {
-    pub fn spec_8kib_32bit_2way() -> CacheSpec {
-        CacheSpec {
-            block_size_bits: 5,
-            set_num_bits: 7,
-            lines_per_set_bits: 1,
+/// A spec indexed with more bits than a page offset has, see [`CacheSpec::require_vipt`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViptError {
+    /// set index plus block offset bits
+    pub index_bits: u8,
+    pub page_bits: u8,
+}
+
+impl fmt::Display for ViptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the cache is indexed by the low {} address bits but only {} are untranslated, use \
+             fewer sets or more ways",
+            self.index_bits, self.page_bits
+        )
+    }
+}
+
+impl Error for ViptError {}
+
+/// An invariant a [`ThrasheReport`] broke, see [`ThrasheReport::sanity_check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SanityError {
+    /// hits and misses don't add up to the accesses
+    Unaccounted {
+        access_count: u64,
+        hits: u64,
+        misses: u64,
+    },
+    /// the per-set counts of `counter` don't add up to its total
+    SetTotals {
+        counter: &'static str,
+        total: u64,
+        per_set: u64,
+    },
+    OverCapacity {
+        occupancy: usize,
+        capacity: usize,
+    },
+    /// a set held more lines than it has ways
+    OverfullSet {
+        set: usize,
+        occupancy: usize,
+        ways: usize,
+    },
+}
+
+impl fmt::Display for SanityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SanityError::Unaccounted {
+                access_count,
+                hits,
+                misses,
+            } => write!(
+                f,
+                "{hits} hits and {misses} misses don't add up to {access_count} accesses"
+            ),
+            SanityError::SetTotals {
+                counter,
+                total,
+                per_set,
+            } => write!(
+                f,
+                "{total} {counter} in total but {per_set} across the sets"
+            ),
+            SanityError::OverCapacity {
+                occupancy,
+                capacity,
+            } => write!(f, "{occupancy} valid lines in a cache of {capacity}"),
+            SanityError::OverfullSet {
+                set,
+                occupancy,
+                ways,
+            } => write!(f, "set {set} held {occupancy} lines but has {ways} ways"),
+        }
+    }
+}
+
+impl Error for SanityError {}
+
+/// The fields of an address as a cache sees them, see [`CacheSpec::bit_layout`]
+///
+/// displays as the usual diagram, high bits first, e.g.
+/// `tag [31:12] 20 bits | index [11:5] 7 bits | offset [4:0] 5 bits`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitLayout {
+    pub tag_bits: u8,
+    pub index_bits: u8,
+    pub offset_bits: u8,
+}
+
+impl fmt::Display for BitLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut low = self.tag_bits + self.index_bits + self.offset_bits;
+        let mut first = true;
+        for (name, bits) in [
+            ("tag", self.tag_bits),
+            ("index", self.index_bits),
+            ("offset", self.offset_bits),
+        ] {
+            // a fully associative cache has no index, a byte sized block no offset
+            if bits == 0 {
+                continue;
+            }
+            if !first {
+                write!(f, " | ")?;
+            }
+            first = false;
+            let high = low - 1;
+            low -= bits;
+            write!(f, "{name} [{high}:{low}] {bits} bits")?;
         }
+        Ok(())
+    }
+}
+
+/// a spec with its replacement policy swapped for the given one
+impl From<(CacheSpec, ReplacementPolicy)> for CacheSpec {
+    fn from((spec, policy): (CacheSpec, ReplacementPolicy)) -> CacheSpec {
+        spec.with_policy(policy)
+    }
+}
+
+impl CacheSpec {
+    pub const fn spec_8kib_32bit_2way() -> CacheSpec {
+        CacheSpec::new(5, 7, 1)
     }
 }
 
-// TODO: remove valid bit, only hit by null pointers - not allowed
-// zeroed so will have the lowest access so will be replaced first anyway
-/// 63 - 32 | 31 - 1 | 0
-/// tag     | access | valid
+// the valid bit can't go, an empty line is all zeroes and tag 0 isn't only null pointers: every
+// address below `conflict_stride` has it, as can any `VirtualAddress`
+/// 63 - 32 | 31 - 3 | 2   | 1     | 0
+/// tag     | access | lru | dirty | valid
 struct CacheLineCompact(AtomicU64);
 
+/// bits of an access kept in a line. Lines are ordered by how far their access is behind the
+/// current one with wrapping arithmetic, which is right as long as none of the lines compared
+/// was last used more than `2^ACCESS_BITS` accesses ago
+const ACCESS_BITS: u32 = 29;
+const ACCESS_MASK: u32 = (1 << ACCESS_BITS) - 1;
+/// where the access starts, after the state bits
+const ACCESS_SHIFT: u32 = 3;
+
 #[derive(Debug, PartialEq, Clone)]
 struct CacheLine {
     tag: u32,
+    /// only the low [`ACCESS_BITS`] are stored
     access: u32,
     valid: bool,
+    /// written to since it was filled, only ever set under [`WritePolicy::WriteBack`]
+    dirty: bool,
+    /// went in as least recently used and hasn't been used since, so it's older than any line
+    /// that has however long ago that was
+    lru: bool,
+}
+
+impl CacheLine {
+    /// a valid line that isn't dirty, used by `access`
+    fn filled(tag: u32, access: u32) -> CacheLine {
+        CacheLine {
+            tag,
+            access,
+            valid: true,
+            dirty: false,
+            lru: false,
+        }
+    }
+
+    /// how many accesses before `epoch` the line was last used, lines that went in as least
+    /// recently used rank above every real age
+    fn age(&self, epoch: u32) -> u32 {
+        if self.lru {
+            u32::MAX
+        } else {
+            epoch.wrapping_sub(self.access) & ACCESS_MASK
+        }
+    }
 }
 
 impl CacheLineCompact {
@@ -73,40 +369,54 @@ impl CacheLineCompact {
     }
 
     fn unpack(val: u64) -> CacheLine {
-        let valid = (val & 1) == 1;
-        let access = (val as u32) >> 1;
-        let tag = (val >> 32) as u32;
-        CacheLine { tag, access, valid }
+        CacheLine {
+            tag: (val >> 32) as u32,
+            access: (val as u32) >> ACCESS_SHIFT,
+            valid: val & 1 == 1,
+            dirty: val & 0b10 != 0,
+            lru: val & 0b100 != 0,
+        }
     }
 
-    /// if matches returns Ok(()) else returns the epoch of that line if its valid or None if invalid
-    pub fn touch_if_matches(
-        &self,
-        cand_tag: u32,
-        epoch_counter: &AtomicU32,
-    ) -> Result<(), Option<u32>> {
+    /// if matches returns Ok(()) else returns the age of that line at `epoch` if its valid or None
+    /// if invalid
+    pub fn touch_if_matches(&self, cand_tag: u32, epoch: u32) -> Result<(), Option<u32>> {
         let val = self.0.load(Ordering::Relaxed);
         let line = Self::unpack(val);
         if line.valid && cand_tag == line.tag {
-            let epoch = epoch_counter.fetch_add(1, Ordering::Relaxed) << 1;
-            let mask: u64 = 0xfffffffe;
-            let new_val = (val & !mask) | epoch as u64;
+            // the access moves on and the line is no longer the least recently used, the tag and
+            // the other state bits stay as they were
+            let mask = ((ACCESS_MASK as u64) << ACCESS_SHIFT) | 0b100;
+            let new_val = (val & !mask) | (((epoch & ACCESS_MASK) as u64) << ACCESS_SHIFT);
             self.0.store(new_val, Ordering::Relaxed);
             Ok(())
         } else if line.valid {
-            Err(Some(line.access))
+            Err(Some(line.age(epoch)))
         } else {
             Err(None)
         }
     }
 
+    fn pack(value: CacheLine) -> u64 {
+        ((value.tag as u64) << 32)
+            | (((value.access & ACCESS_MASK) as u64) << ACCESS_SHIFT)
+            | ((value.lru as u64) << 2)
+            | ((value.dirty as u64) << 1)
+            | value.valid as u64
+    }
+
+    /// sets the dirty bit, leaving the rest of the line as it was
+    pub fn mark_dirty(&self) {
+        self.0.fetch_or(0b10, Ordering::Relaxed);
+    }
+
     pub fn pack_store(&self, value: CacheLine) {
-        let mut encoding = value.tag as u64;
-        encoding <<= 31;
-        encoding |= value.access as u64;
-        encoding <<= 1;
-        encoding |= value.valid as u64;
-        self.0.store(encoding, Ordering::Relaxed);
+        self.0.store(Self::pack(value), Ordering::Relaxed);
+    }
+
+    /// stores the new value returning the line it replaced
+    pub fn pack_swap(&self, value: CacheLine) -> CacheLine {
+        Self::unpack(self.0.swap(Self::pack(value), Ordering::Relaxed))
     }
 }
 
@@ -116,211 +426,3226 @@ impl Clone for CacheLineCompact {
     }
 }
 
-pub struct CacheState {
+/// Outcome of a single access
+pub(crate) struct Access {
+    pub hit: bool,
+    /// block aligned address of the block replaced by a miss
+    pub evicted: Option<u64>,
+}
+
+/// Best line to replace seen so far while scanning a set, invalid lines first then least recently used
+struct Victim {
+    way: usize,
+    /// `None` once an invalid line has been found
+    age: Option<u32>,
+}
+
+impl Victim {
+    fn new(set: &[CacheLineCompact], epoch: u32) -> Victim {
+        Victim {
+            way: 0,
+            age: Some(set[0].fetch_unpack().age(epoch)),
+        }
+    }
+
+    /// `age` is the line's [`CacheLine::age`], or `None` if it is invalid
+    fn consider(&mut self, way: usize, age: Option<u32>) {
+        match (self.age, age) {
+            (None, _) => {}
+            (Some(_), None) => {
+                self.way = way;
+                self.age = None
+            }
+            (Some(best), Some(cand)) => {
+                if cand > best {
+                    self.way = way;
+                    self.age = Some(cand)
+                }
+            }
+        }
+    }
+}
+
+pub struct CacheState<C: Counter = AtomicU64> {
     sets: Vec<Vec<CacheLineCompact>>,
-    epoch: AtomicU32,
+    epoch: AtomicU64,
+    /// the epoch when the stats were last reset
+    stats_epoch: AtomicU64,
     spec: CacheSpec,
-    hits: AtomicU32,
-    misses: AtomicU32,
+    hits: C,
+    misses: C,
+    streaming_accesses: AtomicU64,
+    bank_conflicts: AtomicU64,
+    port_conflicts: AtomicU64,
+    /// only addresses it accepts are simulated
+    address_filter: Option<Box<dyn Fn(u64) -> bool + Send + Sync>>,
+    /// accesses the filter turned away
+    filtered: AtomicU64,
+    replacement: Replacement,
+    tie_break: TieBreak,
+    /// feeds [`TieBreak::Random`]
+    tie_draws: AtomicU64,
+    timeline: Option<TimelineRecorder>,
+    mshrs: Option<MshrFile>,
+    writebacks: Option<Writebacks>,
+    /// writes sent to memory under [`WritePolicy::WriteThrough`]
+    memory_writes: Option<AtomicU64>,
+    /// `2^word_bits` byte words and the total word offset into its line of every miss
+    critical_word: Option<(u8, AtomicU64)>,
+    lifetimes: Option<LifetimeTracker>,
+    /// accesses to each block, keyed by block aligned address
+    frequencies: Option<Mutex<HashMap<u64, u64>>>,
+    budget: Option<AnalysisBudget>,
+    /// whether a tracker left something out to stay within `budget`
+    approximate: AtomicBool,
+    temporal: Option<TemporalTracker>,
+    events: Option<EventBuffer>,
+    oracle: Option<FaOracle>,
+    reuse: Option<ReuseTracker>,
+    /// way holding each tag of every set, only a hint so it's always checked against the line
+    tag_index: Option<Vec<Mutex<HashMap<u32, usize>>>>,
+    /// valid lines in each set
+    set_occupancy: Vec<AtomicUsize>,
+    /// valid lines in every set together
+    valid_lines: AtomicUsize,
+    /// the valid lines there were after each access, summed
+    occupancy_integral: AtomicU64,
+    /// the access that first filled every line, `u64::MAX` until then
+    saturated_at: AtomicU64,
+    /// whether each set has ever held a valid line
+    set_used: Vec<AtomicBool>,
+    /// fills into each set that replaced a valid line
+    set_evictions: Vec<AtomicU64>,
+    set_hits: Vec<AtomicU64>,
+    set_misses: Vec<AtomicU64>,
+    max_set_occupancy: AtomicUsize,
+    most_pressured_set: AtomicUsize,
+    /// the counters at each [`CacheState::mark_phase`]
+    phases: Mutex<Vec<(String, Stats)>>,
+    /// hits and misses of each stream given to [`CacheState::touch_address_stream`]
+    streams: Mutex<HashMap<u16, BatchOutcome>>,
+    /// every address accessed since [`CacheState::enable_capture`]
+    capture: Option<Mutex<Vec<u64>>>,
+    /// the report the last [`CacheState::report_delta`] was taken against
+    last_delta: Mutex<Option<ThrasheReport>>,
 }
 
 impl CacheState {
     pub fn from_spec(spec: CacheSpec) -> CacheState {
+        CacheState::from_spec_with_counters(spec)
+    }
+}
+
+impl<C: Counter + Default> CacheState<C> {
+    /// counts hits and misses with `C` instead of `AtomicU64`s, e.g.
+    /// [`SaturatingU32`](crate::counter::SaturatingU32) to stick at `u32::MAX` like a 32 bit
+    /// hardware counter
+    pub fn from_spec_with_counters(spec: CacheSpec) -> CacheState<C> {
         CacheState {
             sets: vec![vec![CacheLineCompact::new(); spec.lines_per_set()]; spec.set_num()],
-            epoch: AtomicU32::new(0),
+            set_occupancy: (0..spec.set_num()).map(|_| AtomicUsize::new(0)).collect(),
+            valid_lines: AtomicUsize::new(0),
+            occupancy_integral: AtomicU64::new(0),
+            saturated_at: AtomicU64::new(u64::MAX),
+            set_used: (0..spec.set_num())
+                .map(|_| AtomicBool::new(false))
+                .collect(),
+            set_evictions: (0..spec.set_num()).map(|_| AtomicU64::new(0)).collect(),
+            set_hits: (0..spec.set_num()).map(|_| AtomicU64::new(0)).collect(),
+            set_misses: (0..spec.set_num()).map(|_| AtomicU64::new(0)).collect(),
+            epoch: AtomicU64::new(0),
+            stats_epoch: AtomicU64::new(0),
+            replacement: Replacement::new(spec.policy(), spec.set_num()),
+            tie_break: TieBreak::LowestWay,
+            tie_draws: AtomicU64::new(0),
             spec,
-            hits: AtomicU32::new(0),
-            misses: AtomicU32::new(0),
+            hits: C::default(),
+            misses: C::default(),
+            streaming_accesses: AtomicU64::new(0),
+            bank_conflicts: AtomicU64::new(0),
+            port_conflicts: AtomicU64::new(0),
+            address_filter: None,
+            filtered: AtomicU64::new(0),
+            timeline: None,
+            mshrs: None,
+            writebacks: None,
+            memory_writes: None,
+            critical_word: None,
+            lifetimes: None,
+            frequencies: None,
+            budget: None,
+            approximate: AtomicBool::new(false),
+            temporal: None,
+            events: None,
+            oracle: None,
+            reuse: None,
+            tag_index: None,
+            max_set_occupancy: AtomicUsize::new(0),
+            most_pressured_set: AtomicUsize::new(0),
+            phases: Mutex::new(Vec::new()),
+            streams: Mutex::new(HashMap::new()),
+            capture: None,
+            last_delta: Mutex::new(None),
         }
     }
+}
 
-    pub fn touch_address(&self, address: u64) {
-        let (set_index, tag) = self.spec.split(address);
-        let set = &self.sets[set_index as usize];
+impl<C: Counter> CacheState<C> {
+    pub fn spec(&self) -> &CacheSpec {
+        &self.spec
+    }
 
-        let mut oldest = &set[0];
-        let mut oldest_epoch = Some(oldest.fetch_unpack().access);
+    /// records hits and misses for every `window_size` accesses, exposed via [`ThrasheReport::timeline`]
+    pub fn with_timeline(mut self, window_size: u32) -> Self {
+        self.timeline = Some(TimelineRecorder::new(window_size));
+        self
+    }
 
-        for line in set.iter() {
-            match line.touch_if_matches(tag, &self.epoch) {
-                // found entry it has been touched, our work is done
-                Ok(_) => {
-                    self.hits.fetch_add(1, Ordering::Relaxed);
-                    return;
-                }
-                // Err(e) if oldest_epoch > e => {
-                //     oldest_epoch = e;
-                //     oldest = line
-                // }
-                // _ => {}
-                Err(e) => match (oldest_epoch, e) {
-                    (None, _) => {}
-                    (Some(_), None) => {
-                        oldest = line;
-                        oldest_epoch = None
-                    }
-                    (Some(acc_e), Some(cand_e)) => {
-                        if cand_e < acc_e {
-                            oldest = line;
-                            oldest_epoch = None
-                        }
-                    }
-                },
-            }
-        }
+    /// models `count` miss status holding registers, each miss stays outstanding for [`DEFAULT_MSHR_LATENCY`] accesses
+    pub fn with_mshrs(self, count: usize) -> Self {
+        self.with_mshrs_latency(count, DEFAULT_MSHR_LATENCY)
+    }
 
-        let epoch = self.epoch.fetch_add(1, Ordering::Relaxed) << 1;
-        oldest.pack_store(CacheLine {
-            tag,
-            access: epoch,
-            valid: true,
-        });
-        self.misses.fetch_add(1, Ordering::Relaxed);
+    /// like [`CacheState::with_mshrs`] but misses stay outstanding for `latency` accesses
+    pub fn with_mshrs_latency(mut self, count: usize, latency: u32) -> Self {
+        self.mshrs = Some(MshrFile::new(count, latency));
+        self
     }
 
-    pub fn make_report(&self) -> ThrasheReport {
-        let access_count = self.epoch.load(Ordering::Relaxed);
-        let hits = self.hits.load(Ordering::Relaxed);
-        let misses = self.misses.load(Ordering::Relaxed);
-        ThrasheReport {
-            access_count,
-            hits,
-            misses,
-            spec: self.spec.clone(),
+    /// without a write policy writes are only tracked as plain accesses
+    pub fn with_write_policy(mut self, policy: WritePolicy) -> Self {
+        match policy {
+            WritePolicy::WriteBack => self.writebacks = Some(Writebacks::default()),
+            WritePolicy::WriteThrough => self.memory_writes = Some(AtomicU64::new(0)),
         }
+        self
     }
-}
-
-#[derive(Debug)]
-pub struct ThrasheReport {
-    access_count: u32,
-    hits: u32,
-    misses: u32,
-    spec: CacheSpec,
-}
 
-/// Wrapper type that records dereferences in a cache emulation
-pub struct Thrashe<T, C: CacheProvider = GlobalCache> {
-    inner: T,
-    _marker: PhantomData<C>,
-}
+    /// overrides where the spec's replacement policy inserts new blocks
+    pub fn with_insertion(mut self, insertion: Insertion) -> Self {
+        self.replacement.set_insertion(insertion);
+        self
+    }
 
-const _SAME_SIZE: () = assert!(size_of::<usize>() == size_of::<Thrashe<usize>>());
+    /// how to pick between equally good victims, the lowest way unless set
+    pub fn with_tie_break(mut self, tie_break: TieBreak) -> Self {
+        self.tie_break = tie_break;
+        self
+    }
 
-impl<T> Thrashe<T> {
-    pub fn new(value: T) -> Self {
-        Thrashe {
-            inner: value,
-            _marker: PhantomData,
+    /// the way to replace among those of `set` as good as the lowest, `victim`, found at `epoch`
+    fn break_tie(&self, set: &[CacheLineCompact], victim: &Victim, epoch: u32) -> usize {
+        let ties: Vec<usize> = set
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| {
+                let line = line.fetch_unpack();
+                match victim.age {
+                    None => !line.valid,
+                    Some(age) => line.valid && line.age(epoch) == age,
+                }
+            })
+            .map(|(way, _)| way)
+            .collect();
+        match (self.tie_break, ties.as_slice()) {
+            (TieBreak::LowestWay, _) | (_, []) => victim.way,
+            (TieBreak::HighestWay, [.., last]) => *last,
+            (TieBreak::Random, ties) => {
+                let draw = SplitMix64(self.tie_draws.fetch_add(1, Ordering::Relaxed)).next();
+                ties[(draw % ties.len() as u64) as usize]
+            }
         }
     }
-}
-impl<T, C: CacheProvider> Thrashe<T, C> {
-    pub fn prefetch(value: &Self) {
-        if let Some(state) = C::get_cache().read().ok().iter().flat_map(|g| &**g).next() {
-            let address = (value as *const Self) as usize as u64;
-            state.touch_address(address);
-        }
+
+    /// only simulates accesses to addresses `filter` accepts, e.g. to leave out the stack when
+    /// replaying a whole program's trace. The rest are just counted, see
+    /// [`ThrasheReport::filtered_accesses`], and report as misses to the caller
+    pub fn set_address_filter(&mut self, filter: Box<dyn Fn(u64) -> bool + Send + Sync>) {
+        self.address_filter = Some(filter);
     }
-}
 
-impl<T, C: CacheProvider> Deref for Thrashe<T, C> {
-    type Target = T;
+    /// models a line arriving from memory one `2^word_bits` byte word at a time starting from the
+    /// start of the line, with the access going ahead as soon as its own word is in, so a miss
+    /// near the end of a line waits longer. See [`ThrasheReport::estimated_cycles`]
+    pub fn with_critical_word_first(mut self, word_bits: u8) -> Self {
+        assert!(
+            word_bits <= self.spec.block_size_bits,
+            "words can't be bigger than blocks"
+        );
+        self.critical_word = Some((word_bits, AtomicU64::new(0)));
+        self
+    }
 
-    fn deref(&self) -> &Self::Target {
-        if let Some(state) = C::get_cache().read().ok().iter().flat_map(|g| &**g).next() {
-            let address = (self as *const Self) as usize as u64;
-            state.touch_address(address);
-        }
+    /// records how many accesses each line survives before being evicted, exposed via [`ThrasheReport::eviction_ages`]
+    pub fn enable_lifetime_tracking(&mut self) {
+        self.lifetimes = Some(LifetimeTracker::new(
+            self.spec.set_num(),
+            self.spec.lines_per_set(),
+        ));
+    }
 
-        &self.inner
+    /// tracks the timestamps given to [`CacheState::touch_address_at`], flagging blocks that miss
+    /// within `near_miss_window` of being evicted, exposed via [`ThrasheReport::temporal`]
+    pub fn with_timestamps(mut self, near_miss_window: u64) -> Self {
+        self.temporal = Some(TemporalTracker::new(near_miss_window));
+        self
     }
-}
 
-trait SyncAssert: Sync {}
-impl<T: Sync> SyncAssert for Thrashe<T> {}
+    /// keeps the last `capacity` hits, misses and evictions, exposed via [`ThrasheReport::recent_events`]
+    pub fn with_event_buffer(mut self, capacity: usize) -> Self {
+        self.events = Some(EventBuffer::new(capacity));
+        self
+    }
 
-trait SendAssert: Send {}
-impl<T: Send> SendAssert for Thrashe<T> {}
+    /// runs a fully associative LRU cache with as many lines alongside this one, so the misses
+    /// caused by limited associativity show up as the difference to [`ThrasheReport::oracle_misses`]
+    pub fn with_fa_oracle(mut self) -> Self {
+        self.oracle = Some(FaOracle::new(self.spec.capacity_lines()));
+        self
+    }
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    /// tracks the stack distance of every access, exposed via [`ThrasheReport::mean_hit_reuse_distance`]
+    pub fn with_reuse_distances(mut self) -> Self {
+        self.reuse = Some(ReuseTracker::new());
+        self
+    }
 
-    #[test]
-    fn basic() {
-        GlobalCache::configure(CacheSpec::spec_8kib_32bit_2way());
-        let foo = Thrashe::new(42);
+    /// finds hits through a map from tag to way rather than searching the set, worth it for caches
+    /// with hundreds of ways like a large fully associative one. Misses still search for a victim
+    pub fn with_tag_index(mut self) -> Self {
+        self.tag_index = Some(
+            (0..self.spec.set_num())
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+        );
+        self
+    }
 
-        let _ = *foo;
-        let _ = *foo;
+    /// caps what the block frequency, reuse distance and timestamp trackers hold, whenever they are
+    /// enabled
+    pub fn with_analysis_budget(mut self, budget: AnalysisBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
 
-        let report = GlobalCache::finish().unwrap();
-        println!("{:?}", report);
-        assert_eq!(report.hits, 1);
-        assert_eq!(report.misses, 1);
+    fn max_tracked_blocks(&self) -> usize {
+        self.budget.map_or(usize::MAX, |b| b.max_tracked_blocks)
     }
 
-    #[test]
-    fn trashing() {
-        let spec = CacheSpec::spec_8kib_32bit_2way();
-        let cache = CacheState::from_spec(spec.clone());
-        let array_size = 512;
-        let element_size = 8;
+    fn record_approximation(&self, dropped: bool) {
+        if dropped {
+            self.approximate.store(true, Ordering::Relaxed);
+        }
+    }
 
-        let a_base = 4200;
-        let b_base = a_base + array_size * element_size;
-        let c_base = b_base + array_size * element_size;
+    /// records the address of every access from now on, to [`CacheState::replay`] it later e.g.
+    /// against a different spec, see [`CacheState::take_capture`]
+    pub fn enable_capture(&mut self) {
+        self.capture = Some(Mutex::new(Vec::new()));
+    }
 
-        for i in 0..12 {
-            let a_addr = a_base + element_size * i;
-            let b_addr = b_base + element_size * i;
-            let c_addr = c_base + element_size * i;
+    /// the addresses captured so far in the order they were accessed, leaving the capture empty
+    /// but still recording. `None` if capturing was never enabled
+    pub fn take_capture(&self) -> Option<Vec<u64>> {
+        let capture = self.capture.as_ref()?;
+        Some(std::mem::take(&mut *capture.lock().unwrap()))
+    }
 
-            cache.touch_address(a_addr);
-            cache.touch_address(b_addr);
-            cache.touch_address(c_addr);
+    /// counts accesses to every block, exposed via [`ThrasheReport::top_tags`]
+    ///
+    /// this keeps an entry for every distinct block ever touched, roughly 16 bytes each
+    /// plus hash map overhead, so memory grows with the footprint of the trace unless capped by
+    /// [`CacheState::with_analysis_budget`]
+    pub fn enable_frequency_tracking(&mut self) {
+        self.frequencies = Some(Mutex::new(HashMap::new()));
+    }
+
+    /// returns whether the access hit
+    pub fn touch_address(&self, address: u64) -> bool {
+        self.access(address, false, true).hit
+    }
+
+    /// returns whether the access hit
+    pub fn touch_address_write(&self, address: u64) -> bool {
+        self.access(address, true, true).hit
+    }
+
+    /// an access at a caller supplied logical time, see [`CacheState::with_timestamps`]
+    ///
+    /// returns whether the access hit
+    pub fn touch_address_at(&self, address: u64, timestamp: u64) -> bool {
+        let access = self.access(address, false, true);
+        if let Some(temporal) = &self.temporal {
+            let block = address & !(self.spec.block_size() as u64 - 1);
+            let dropped = temporal.record(
+                block,
+                timestamp,
+                access.hit,
+                access.evicted,
+                self.max_tracked_blocks(),
+            );
+            self.record_approximation(dropped);
         }
+        access.hit
+    }
 
-        let report = cache.make_report();
-        assert_eq!(report.access_count, 36);
-        assert_eq!(report.spec.size(), 8192);
-        assert_eq!(report.hits, 0);
-        assert_eq!(report.misses, 36);
+    /// two accesses issued in the same cycle, which conflict if different blocks need the same bank
+    ///
+    /// returns whether each access hit
+    pub fn touch_pair(&self, address_a: u64, address_b: u64) -> (bool, bool) {
+        let block_bits = self.spec.block_size_bits;
+        if self.spec.bank_of(address_a) == self.spec.bank_of(address_b)
+            && address_a >> block_bits != address_b >> block_bits
+        {
+            self.bank_conflicts.fetch_add(1, Ordering::Relaxed);
+        }
+        (self.touch_address(address_a), self.touch_address(address_b))
     }
 
-    #[test]
-    fn linear_access() {
-        let spec = CacheSpec::spec_8kib_32bit_2way();
-        let cache = CacheState::from_spec(spec.clone());
-        let element_size = 8;
+    /// accesses issued in the same cycle, any beyond the spec's [`CacheSpec::ports`] stall and are
+    /// counted as port conflicts, though all of them are still carried out
+    pub fn touch_cycle(&self, accesses: &[CacheAccess]) -> BatchOutcome {
+        let stalled = accesses.len().saturating_sub(self.spec.ports());
+        self.port_conflicts
+            .fetch_add(stalled as u64, Ordering::Relaxed);
+        let mut outcome = BatchOutcome::default();
+        for access in accesses {
+            if self.access(access.address, access.write, true).hit {
+                outcome.hits += 1;
+            } else {
+                outcome.misses += 1;
+            }
+        }
+        outcome
+    }
 
-        let a_base = 4200;
+    /// a streaming load that bypasses the cache, like `_mm_stream_load`, it is counted
+    /// separately and neither fills nor evicts anything
+    pub fn touch_address_nontemporal(&self, _address: u64) {
+        self.streaming_accesses.fetch_add(1, Ordering::Relaxed);
+    }
 
-        for i in 0..128 {
-            let a_addr = a_base + element_size * i;
-            cache.touch_address(a_addr);
+    /// [`CacheState::touch_address`] on behalf of stream `stream_id`, e.g. one of several arrays
+    /// walked together, so [`ThrasheReport::per_stream_stats`] can tell which of them misses
+    pub fn touch_address_stream(&self, address: u64, stream_id: u16) -> bool {
+        let hit = self.touch_address(address);
+        let mut streams = self.streams.lock().unwrap();
+        let stats = streams.entry(stream_id).or_default();
+        if hit {
+            stats.hits += 1;
+        } else {
+            stats.misses += 1;
         }
+        hit
+    }
 
-        let report = cache.make_report();
-        assert_eq!(report.access_count, 128);
-        assert_eq!(report.spec.size(), 8192);
-        assert_eq!(report.hits, 96);
-        assert_eq!(report.misses, 32);
+    /// counts an access, on a miss the block is only brought in if `allocate` is set
+    pub(crate) fn access(&self, address: u64, write: bool, allocate: bool) -> Access {
+        if let Some(capture) = &self.capture {
+            capture.lock().unwrap().push(address);
+        }
+        if let Some(filter) = &self.address_filter
+            && !filter(address)
+        {
+            self.filtered.fetch_add(1, Ordering::Relaxed);
+            return Access {
+                hit: false,
+                evicted: None,
+            };
+        }
+        let access = self.access_lines(address, write, allocate);
+        self.occupancy_integral.fetch_add(
+            self.valid_lines.load(Ordering::Relaxed) as u64,
+            Ordering::Relaxed,
+        );
+        if let (true, Some(memory_writes)) = (write, &self.memory_writes) {
+            memory_writes.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(reuse) = &self.reuse {
+            let dropped = reuse.record(
+                address >> self.spec.block_size_bits,
+                access.hit,
+                self.max_tracked_blocks(),
+            );
+            self.record_approximation(dropped);
+        }
+        access
     }
 
-    #[test]
-    fn pack_unpack() {
-        let line = CacheLineCompact::new();
-        let val = CacheLine {
-            tag: 0xABCDEFAB,
-            access: 0x0123456,
-            valid: true,
+    fn access_lines(&self, address: u64, write: bool, allocate: bool) -> Access {
+        let (set_index, tag) = self.spec.split(address);
+        let set = &self.sets[set_index as usize];
+        // lines keep the low `ACCESS_BITS` of it and trackers the low 32, both order stamps by
+        // how far they're behind the current epoch in wrapping arithmetic
+        let epoch = self.epoch.fetch_add(1, Ordering::Relaxed) as u32;
+        if let Some(frequencies) = &self.frequencies {
+            let block = self.spec.join(set_index, tag);
+            let mut frequencies = frequencies.lock().unwrap();
+            let full = frequencies.len() >= self.max_tracked_blocks();
+            match frequencies.get_mut(&block) {
+                Some(count) => *count += 1,
+                None if full => self.record_approximation(true),
+                None => {
+                    frequencies.insert(block, 1);
+                }
+            }
+        }
+        if let Some(oracle) = &self.oracle {
+            oracle.access(address >> self.spec.block_size_bits);
+        }
+
+        if let Some(index) = &self.tag_index {
+            let way = index[set_index as usize].lock().unwrap().get(&tag).copied();
+            if let Some(way) = way
+                && set[way].touch_if_matches(tag, epoch).is_ok()
+            {
+                return self.record_hit(set_index, way, epoch, address, write);
+            }
+        }
+
+        let victim_way = if let [line] = set.as_slice() {
+            // direct mapped, the only line either matches or gets replaced, there's nothing to search
+            if line.touch_if_matches(tag, epoch).is_ok() {
+                return self.record_hit(set_index, 0, epoch, address, write);
+            }
+            0
+        } else {
+            let mut victim = Victim::new(set, epoch);
+            for (way, line) in set.iter().enumerate() {
+                match line.touch_if_matches(tag, epoch) {
+                    // found entry it has been touched, our work is done
+                    Ok(_) => return self.record_hit(set_index, way, epoch, address, write),
+                    Err(e) => victim.consider(way, e),
+                }
+            }
+            if self.tie_break != TieBreak::LowestWay {
+                victim.way = self.break_tie(set, &victim, epoch);
+            }
+            match victim.age {
+                None => victim.way,
+                Some(_) => self.replacement.victim(tag, victim.way, || {
+                    set.iter()
+                        .map(|line| line.fetch_unpack().age(epoch))
+                        .collect()
+                }),
+            }
         };
-        line.pack_store(val.clone());
-        let returned = line.fetch_unpack();
 
-        assert_eq!(val, returned)
+        self.misses.increment();
+        self.set_misses[set_index as usize].fetch_add(1, Ordering::Relaxed);
+        self.record_timeline(epoch, false);
+        self.record_event(CacheEvent::Miss { address });
+        self.replacement.record_miss(set_index as usize);
+        if let Some((word_bits, offsets)) = &self.critical_word {
+            let offset = address & (self.spec.block_size() as u64 - 1);
+            offsets.fetch_add(offset >> word_bits, Ordering::Relaxed);
+        }
+        if let Some(mshrs) = &self.mshrs {
+            mshrs.record_miss(address >> self.spec.block_size_bits, epoch);
+        }
+
+        let evicted = if allocate {
+            self.fill_way(set_index, victim_way, tag, epoch, write)
+        } else {
+            None
+        };
+        Access {
+            hit: false,
+            evicted,
+        }
+    }
+
+    fn record_hit(
+        &self,
+        set_index: u32,
+        way: usize,
+        epoch: u32,
+        address: u64,
+        write: bool,
+    ) -> Access {
+        self.hits.increment();
+        self.set_hits[set_index as usize].fetch_add(1, Ordering::Relaxed);
+        self.record_timeline(epoch, true);
+        self.record_event(CacheEvent::Hit { address });
+        if write && self.writebacks.is_some() {
+            self.sets[set_index as usize][way].mark_dirty();
+        }
+        Access {
+            hit: true,
+            evicted: None,
+        }
+    }
+
+    /// brings a block in without counting it as an access, e.g. one demoted from the level above
+    ///
+    /// returns the address of the block it replaced
+    pub(crate) fn fill(&self, address: u64) -> Option<u64> {
+        let (set_index, tag) = self.spec.split(address);
+        let set = &self.sets[set_index as usize];
+        let epoch = self.epoch.load(Ordering::Relaxed) as u32;
+
+        let mut victim = Victim::new(set, epoch);
+        for (way, line) in set.iter().enumerate() {
+            let line = line.fetch_unpack();
+            if line.valid && line.tag == tag {
+                return None;
+            }
+            victim.consider(way, line.valid.then(|| line.age(epoch)));
+        }
+
+        self.fill_way(set_index, victim.way, tag, epoch, false)
+    }
+
+    /// brings in every block `other` holds without counting accesses, e.g. to make a lower level
+    /// a superset of the level above before an inclusive hierarchy starts
+    ///
+    /// blocks go in from the least to the most recently used in `other`, evicting as `self`'s
+    /// sets fill up, and a block of `other` larger than `self`'s fills all the blocks it covers
+    pub fn prefill_from(&self, other: &CacheState) {
+        let epoch = other.epoch.load(Ordering::Relaxed) as u32;
+        let mut blocks = Vec::with_capacity(other.occupancy());
+        for (set_index, set) in other.sets.iter().enumerate() {
+            for line in set {
+                let line = line.fetch_unpack();
+                if line.valid {
+                    let block = other.spec.join(set_index as u32, line.tag);
+                    blocks.push((Reverse(line.age(epoch)), block));
+                }
+            }
+        }
+        blocks.sort_unstable();
+
+        let (theirs, mine) = (
+            other.spec.block_size() as u64,
+            self.spec.block_size() as u64,
+        );
+        for (_, block) in blocks {
+            for offset in (0..theirs).step_by(mine as usize) {
+                self.fill(block + offset);
+            }
+        }
+    }
+
+    /// returns the address of the block that was replaced, if there was one
+    fn fill_way(
+        &self,
+        set_index: u32,
+        way: usize,
+        tag: u32,
+        epoch: u32,
+        write: bool,
+    ) -> Option<u64> {
+        let set = set_index as usize;
+        let line = CacheLine {
+            dirty: write && self.writebacks.is_some(),
+            // the lru bit makes the block the first to go, however old the rest of the set is
+            lru: !self.replacement.insert_mru(set),
+            ..CacheLine::filled(tag, epoch)
+        };
+        let previous = self.sets[set][way].pack_swap(line);
+        let evicted = previous.valid;
+        if let Some(writebacks) = &self.writebacks
+            && evicted
+        {
+            writebacks.evicted(previous.dirty);
+        }
+        if let Some(lifetimes) = &self.lifetimes {
+            lifetimes.fill(set, way, epoch, evicted);
+        }
+        if let Some(index) = &self.tag_index {
+            let mut index = index[set].lock().unwrap();
+            if evicted && index.get(&previous.tag) == Some(&way) {
+                index.remove(&previous.tag);
+            }
+            index.insert(tag, way);
+        }
+        if !evicted {
+            self.record_set_fill(set);
+            return None;
+        }
+        self.set_evictions[set].fetch_add(1, Ordering::Relaxed);
+        let block = self.spec.join(set_index, previous.tag);
+        self.record_event(CacheEvent::Eviction { block });
+        Some(block)
+    }
+
+    /// whether the block holding `address` is cached, without counting an access
+    pub fn contains(&self, address: u64) -> bool {
+        let (set_index, tag) = self.spec.split(address);
+        self.sets[set_index as usize].iter().any(|line| {
+            let line = line.fetch_unpack();
+            line.valid && line.tag == tag
+        })
+    }
+
+    /// what each way of set `set_index` holds, in way order and `None` where it's invalid,
+    /// without counting an access
+    pub fn ways(&self, set_index: usize) -> Vec<Option<ResidentLine>> {
+        self.sets[set_index]
+            .iter()
+            .map(|line| {
+                let line = line.fetch_unpack();
+                line.valid.then(|| ResidentLine {
+                    block: self.spec.join(set_index as u32, line.tag),
+                    tag: line.tag,
+                    last_access: line.access,
+                })
+            })
+            .collect()
+    }
+
+    /// where the block holding `address` is in its set's recency order without counting an
+    /// access, 0 for the most recently used, `None` if it isn't cached
+    ///
+    /// among equally recent lines the lowest way ranks oldest, as it's the one evicted first
+    pub fn recency_rank(&self, address: u64) -> Option<usize> {
+        let (set_index, tag) = self.spec.split(address);
+        let epoch = self.epoch.load(Ordering::Relaxed) as u32;
+        let lines: Vec<_> = self.sets[set_index as usize]
+            .iter()
+            .map(|line| line.fetch_unpack())
+            .collect();
+        let way = lines.iter().position(|l| l.valid && l.tag == tag)?;
+        let key = |way: usize| (Reverse(lines[way].age(epoch)), way);
+        Some(
+            (0..lines.len())
+                .filter(|&other| lines[other].valid && key(other) > key(way))
+                .count(),
+        )
+    }
+
+    /// drops the block holding `address`, returns whether it was cached
+    pub fn invalidate(&self, address: u64) -> bool {
+        let (set_index, tag) = self.spec.split(address);
+        for (way, line) in self.sets[set_index as usize].iter().enumerate() {
+            let value = line.fetch_unpack();
+            if value.valid && value.tag == tag {
+                line.pack_store(CacheLine {
+                    valid: false,
+                    ..value
+                });
+                self.set_occupancy[set_index as usize].fetch_sub(1, Ordering::Relaxed);
+                self.valid_lines.fetch_sub(1, Ordering::Relaxed);
+                if let Some(index) = &self.tag_index {
+                    let mut index = index[set_index as usize].lock().unwrap();
+                    if index.get(&tag) == Some(&way) {
+                        index.remove(&tag);
+                    }
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// drops every block in set `set_index`, like a set flush instruction, returning how many were
+    /// cached
+    pub fn invalidate_set(&self, set_index: usize) -> usize {
+        let mut dropped = 0;
+        for line in &self.sets[set_index] {
+            let value = line.fetch_unpack();
+            if value.valid {
+                line.pack_store(CacheLine {
+                    valid: false,
+                    ..value
+                });
+                dropped += 1;
+            }
+        }
+        self.set_occupancy[set_index].fetch_sub(dropped, Ordering::Relaxed);
+        self.valid_lines.fetch_sub(dropped, Ordering::Relaxed);
+        if let Some(index) = &self.tag_index {
+            index[set_index].lock().unwrap().clear();
+        }
+        dropped
+    }
+
+    pub fn replay(&self, trace: &[u64]) {
+        for &address in trace {
+            self.touch_address(address);
+        }
+    }
+
+    /// touches every block overlapping the `len` bytes from `address`
+    pub fn touch_range(&self, address: u64, len: u64) -> BatchOutcome {
+        if len == 0 {
+            return BatchOutcome::default();
+        }
+        let block_size = self.spec.block_size() as u64;
+        let first = address & !(block_size - 1);
+        let last = address.saturating_add(len - 1);
+        self.touch_iter((first..=last).step_by(block_size as usize))
+    }
+
+    /// touches element `index` of an array of `elem_size` byte elements starting at `base`,
+    /// every block of it if it straddles several
+    ///
+    /// the element's address is worked out in wrapping u64 arithmetic, so an offset past the
+    /// top of the address space comes back round from 0 like the address of a wrapping pointer
+    pub fn touch_indexed(&self, base: u64, index: usize, elem_size: usize) -> BatchOutcome {
+        let offset = (index as u64).wrapping_mul(elem_size as u64);
+        self.touch_range(base.wrapping_add(offset), elem_size as u64)
+    }
+
+    /// replays `trace` until `predicate` holds for the stats after an access, returning that
+    /// access's index, or the length of the trace if it never does
+    pub fn replay_until(&self, trace: &[u64], mut predicate: impl FnMut(&Stats) -> bool) -> usize {
+        for (i, &address) in trace.iter().enumerate() {
+            self.touch_address(address);
+            if predicate(&self.stats_snapshot()) {
+                return i;
+            }
+        }
+        trace.len()
+    }
+
+    /// touches every address, returning the hits and misses of just this batch
+    pub fn touch_iter<I: IntoIterator<Item = u64>>(&self, addrs: I) -> BatchOutcome {
+        let mut outcome = BatchOutcome::default();
+        for address in addrs {
+            if self.touch_address(address) {
+                outcome.hits += 1;
+            } else {
+                outcome.misses += 1;
+            }
+        }
+        outcome
+    }
+
+    fn record_set_fill(&self, set_index: usize) {
+        let valid = self.set_occupancy[set_index].fetch_add(1, Ordering::Relaxed) + 1;
+        if self.valid_lines.fetch_add(1, Ordering::Relaxed) + 1 == self.spec.capacity_lines() {
+            let epoch = self.epoch.load(Ordering::Relaxed);
+            let _ = self.saturated_at.compare_exchange(
+                u64::MAX,
+                epoch,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        }
+        self.set_used[set_index].store(true, Ordering::Relaxed);
+        if self.max_set_occupancy.fetch_max(valid, Ordering::Relaxed) < valid {
+            self.most_pressured_set.store(set_index, Ordering::Relaxed);
+        }
+    }
+
+    fn record_timeline(&self, epoch: u32, hit: bool) {
+        if let Some(timeline) = &self.timeline {
+            timeline.record(epoch, hit);
+        }
+    }
+
+    fn record_event(&self, event: CacheEvent) {
+        if let Some(events) = &self.events {
+            events.record(event);
+        }
+    }
+
+    /// number of currently valid lines
+    pub fn occupancy(&self) -> usize {
+        self.set_occupancy
+            .iter()
+            .map(|valid| valid.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    fn access_count(&self) -> u64 {
+        self.epoch
+            .load(Ordering::Relaxed)
+            .wrapping_sub(self.stats_epoch.load(Ordering::Relaxed))
+    }
+
+    /// zeroes the access counters, including the per-set ones, while keeping the cached lines
+    /// and their recency, e.g. to stop a warm up phase counting towards the results
+    ///
+    /// the opt-in trackers like the timeline keep what they recorded
+    pub fn reset_stats(&self) {
+        self.stats_epoch
+            .store(self.epoch.load(Ordering::Relaxed), Ordering::Relaxed);
+        self.hits.reset();
+        self.misses.reset();
+        for counter in [
+            &self.streaming_accesses,
+            &self.bank_conflicts,
+            &self.port_conflicts,
+            &self.filtered,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        if let Some((_, offsets)) = &self.critical_word {
+            offsets.store(0, Ordering::Relaxed);
+        }
+        self.occupancy_integral.store(0, Ordering::Relaxed);
+        for set_index in 0..self.spec.set_num() {
+            self.reset_set_stats(set_index);
+        }
+        // they hold the counters from before, so would give nonsense deltas
+        self.phases.lock().unwrap().clear();
+        self.streams.lock().unwrap().clear();
+        *self.last_delta.lock().unwrap() = None;
+    }
+
+    /// zeroes just the per-set counters of `set_index`, leaving its lines and the totals alone
+    pub fn reset_set_stats(&self, set_index: usize) {
+        for counter in [
+            &self.set_hits[set_index],
+            &self.set_misses[set_index],
+            &self.set_evictions[set_index],
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn hit_count(&self) -> u64 {
+        self.hits.get()
+    }
+
+    fn miss_count(&self) -> u64 {
+        self.misses.get()
+    }
+
+    /// starts a phase called `name` at the current access, it runs until the next marker or the
+    /// report, see [`ThrasheReport::phases`]
+    pub fn mark_phase(&self, name: &str) {
+        let stats = self.stats_snapshot();
+        self.phases.lock().unwrap().push((name.to_owned(), stats));
+    }
+
+    fn phase_stats(&self) -> Vec<PhaseStats> {
+        let phases = self.phases.lock().unwrap();
+        let end = ("".to_owned(), self.stats_snapshot());
+        phases
+            .iter()
+            .zip(phases.iter().skip(1).chain([&end]))
+            .map(|((name, start), (_, end))| PhaseStats {
+                name: name.clone(),
+                access_count: end.access_count.saturating_sub(start.access_count),
+                hits: end.hits.saturating_sub(start.hits),
+                misses: end.misses.saturating_sub(start.misses),
+            })
+            .collect()
+    }
+
+    /// a report of just the accesses since the previous call, or since the start or the last
+    /// [`CacheState::reset_stats`] for the first
+    ///
+    /// only the counters are deltas, the state of the cache like its occupancy and the opt-in
+    /// trackers are as of now
+    pub fn report_delta(&self) -> ThrasheReport {
+        let mut last = self.last_delta.lock().unwrap();
+        let report = self.make_report();
+        let delta = match &*last {
+            Some(previous) => report.clone().since(previous),
+            None => report.clone(),
+        };
+        *last = Some(report);
+        delta
+    }
+
+    /// just the counters, cheap enough to poll without building a whole report
+    pub fn stats_snapshot(&self) -> Stats {
+        Stats {
+            access_count: self.access_count(),
+            hits: self.hit_count(),
+            misses: self.miss_count(),
+            streaming_accesses: self.streaming_accesses.load(Ordering::Relaxed),
+            occupancy: self.occupancy(),
+        }
+    }
+
+    /// copies every scalar statistic into `out`, without allocating like [`CacheState::make_report`]
+    pub fn write_report_into(&self, out: &mut ReportBuf) {
+        let max_set_occupancy = self.max_set_occupancy.load(Ordering::Relaxed);
+        *out = ReportBuf {
+            access_count: self.access_count(),
+            hits: self.hit_count(),
+            misses: self.miss_count(),
+            streaming_accesses: self.streaming_accesses.load(Ordering::Relaxed),
+            bank_conflicts: self.bank_conflicts.load(Ordering::Relaxed),
+            port_conflicts: self.port_conflicts.load(Ordering::Relaxed),
+            filtered_accesses: self.filtered.load(Ordering::Relaxed),
+            occupancy: self.occupancy(),
+            max_set_occupancy,
+            most_pressured_set: (max_set_occupancy > 0)
+                .then(|| self.most_pressured_set.load(Ordering::Relaxed)),
+            writebacks: self.writebacks.as_ref().map(|w| w.count()),
+            memory_writes: self
+                .memory_writes
+                .as_ref()
+                .map(|w| w.load(Ordering::Relaxed)),
+        };
+    }
+
+    /// every set on its own row, a valid way shows as `tag@last access` and an invalid one as `-`
+    ///
+    /// only reads the lines, so recency is left alone
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        for (index, set) in self.sets.iter().enumerate() {
+            write!(out, "set {index:>5} |").unwrap();
+            for line in set {
+                let line = line.fetch_unpack();
+                if line.valid {
+                    write!(out, " {:#010x}@{:<10} |", line.tag, line.access).unwrap();
+                } else {
+                    write!(out, " {:<21} |", "-").unwrap();
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    pub fn make_report(&self) -> ThrasheReport {
+        let access_count = self.access_count();
+        let hits = self.hit_count();
+        let misses = self.miss_count();
+        ThrasheReport {
+            access_count,
+            hits,
+            misses,
+            streaming_accesses: self.streaming_accesses.load(Ordering::Relaxed),
+            bank_conflicts: self.bank_conflicts.load(Ordering::Relaxed),
+            port_conflicts: self.port_conflicts.load(Ordering::Relaxed),
+            filtered: self.filtered.load(Ordering::Relaxed),
+            occupancy: self.occupancy(),
+            occupancy_integral: self.occupancy_integral.load(Ordering::Relaxed),
+            first_saturation: match self.saturated_at.load(Ordering::Relaxed) {
+                u64::MAX => None,
+                epoch => Some(epoch),
+            },
+            max_set_occupancy: self.max_set_occupancy.load(Ordering::Relaxed),
+            most_pressured_set: self.most_pressured_set.load(Ordering::Relaxed),
+            sets_used: self
+                .set_used
+                .iter()
+                .filter(|used| used.load(Ordering::Relaxed))
+                .count(),
+            evictions_per_set: self
+                .set_evictions
+                .iter()
+                .map(|e| e.load(Ordering::Relaxed))
+                .collect(),
+            hits_per_set: self
+                .set_hits
+                .iter()
+                .map(|h| h.load(Ordering::Relaxed))
+                .collect(),
+            misses_per_set: self
+                .set_misses
+                .iter()
+                .map(|m| m.load(Ordering::Relaxed))
+                .collect(),
+            spec: self.spec.clone(),
+            timeline: self.timeline.as_ref().map(|t| t.snapshot()),
+            mshr_stats: self.mshrs.as_ref().map(|m| m.stats()),
+            writebacks: self.writebacks.as_ref().map(|w| w.count()),
+            memory_writes: self
+                .memory_writes
+                .as_ref()
+                .map(|w| w.load(Ordering::Relaxed)),
+            miss_word_offsets: self
+                .critical_word
+                .as_ref()
+                .map(|(_, offsets)| offsets.load(Ordering::Relaxed)),
+            eviction_ages: self.lifetimes.as_ref().map(|l| l.snapshot()),
+            temporal: self.temporal.as_ref().map(|t| t.stats()),
+            approximate: self.approximate.load(Ordering::Relaxed),
+            events: self.events.as_ref().map(|e| e.snapshot()),
+            oracle: self.oracle.as_ref().map(|o| o.stats()),
+            mean_hit_reuse_distance: self.reuse.as_ref().and_then(|r| r.mean_hit_distance()),
+            reuse_distances: self.reuse.as_ref().map(|r| r.sketch()),
+            block_frequencies: self.frequencies.as_ref().map(|f| {
+                let mut blocks: Vec<_> = f.lock().unwrap().iter().map(|(&b, &c)| (b, c)).collect();
+                blocks.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+                blocks
+            }),
+            phases: self.phase_stats(),
+            streams: {
+                let mut streams: Vec<_> = self
+                    .streams
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(&id, &stats)| (id, stats))
+                    .collect();
+                streams.sort_unstable_by_key(|&(id, _)| id);
+                streams
+            },
+            skipped_accesses: None,
+        }
+    }
+}
+
+/// The counters of a [`CacheState`] at one point in time, see [`CacheState::stats_snapshot`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub access_count: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub streaming_accesses: u64,
+    pub occupancy: usize,
+}
+
+/// Caller owned storage for the scalar parts of a report, see [`CacheState::write_report_into`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReportBuf {
+    pub access_count: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub streaming_accesses: u64,
+    pub bank_conflicts: u64,
+    pub port_conflicts: u64,
+    pub filtered_accesses: u64,
+    pub occupancy: usize,
+    pub max_set_occupancy: usize,
+    pub most_pressured_set: Option<usize>,
+    pub writebacks: Option<u64>,
+    pub memory_writes: Option<u64>,
+}
+
+/// The counters between one [`CacheState::mark_phase`] and the next
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PhaseStats {
+    pub name: String,
+    pub access_count: u64,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// A valid line of a set, see [`CacheState::ways`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResidentLine {
+    /// address of the first byte of the block
+    pub block: u64,
+    pub tag: u32,
+    /// the access it was last used by, counted from when the cache was created but kept to its
+    /// low 29 bits like the line's own copy, so it goes back round to 0 every 2^29 accesses
+    pub last_access: u32,
+}
+
+/// One access of a [`CacheState::touch_cycle`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheAccess {
+    pub address: u64,
+    pub write: bool,
+}
+
+impl CacheAccess {
+    pub fn read(address: u64) -> CacheAccess {
+        CacheAccess {
+            address,
+            write: false,
+        }
+    }
+
+    pub fn write(address: u64) -> CacheAccess {
+        CacheAccess {
+            address,
+            write: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchOutcome {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl BatchOutcome {
+    pub fn accesses(&self) -> u64 {
+        self.hits + self.misses
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThrasheReport {
+    access_count: u64,
+    hits: u64,
+    misses: u64,
+    streaming_accesses: u64,
+    bank_conflicts: u64,
+    port_conflicts: u64,
+    filtered: u64,
+    occupancy: usize,
+    /// the valid lines after each access, summed
+    occupancy_integral: u64,
+    first_saturation: Option<u64>,
+    max_set_occupancy: usize,
+    most_pressured_set: usize,
+    sets_used: usize,
+    evictions_per_set: Vec<u64>,
+    hits_per_set: Vec<u64>,
+    misses_per_set: Vec<u64>,
+    spec: CacheSpec,
+    timeline: Option<Timeline>,
+    mshr_stats: Option<MshrStats>,
+    writebacks: Option<u64>,
+    memory_writes: Option<u64>,
+    /// summed over every miss, in words
+    miss_word_offsets: Option<u64>,
+    eviction_ages: Option<LifetimeHistogram>,
+    temporal: Option<TemporalStats>,
+    approximate: bool,
+    /// oldest first, and how many were dropped to make room
+    events: Option<(Vec<CacheEvent>, u64)>,
+    /// hits and misses of the fully associative oracle
+    oracle: Option<(u64, u64)>,
+    mean_hit_reuse_distance: Option<f64>,
+    reuse_distances: Option<ReuseDistanceSketch>,
+    /// most accessed first
+    block_frequencies: Option<Vec<(u64, u64)>>,
+    phases: Vec<PhaseStats>,
+    /// sorted by stream id
+    streams: Vec<(u16, BatchOutcome)>,
+    /// accesses a lossy provider dropped instead of recording
+    skipped_accesses: Option<u64>,
+}
+
+impl ThrasheReport {
+    /// adds up the reports of two caches with the same spec, e.g. one per thread
+    ///
+    /// statistics that can't be summed, like timelines and event buffers, are dropped
+    pub fn merge(mut self, other: &ThrasheReport) -> ThrasheReport {
+        assert_eq!(
+            self.spec, other.spec,
+            "can only merge reports of the same spec"
+        );
+        let add = |a: Option<u64>, b: Option<u64>| a.zip(b).map(|(a, b)| a + b);
+
+        self.access_count += other.access_count;
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.streaming_accesses += other.streaming_accesses;
+        self.bank_conflicts += other.bank_conflicts;
+        self.port_conflicts += other.port_conflicts;
+        self.filtered += other.filtered;
+        self.occupancy += other.occupancy;
+        self.occupancy_integral += other.occupancy_integral;
+        if other.max_set_occupancy > self.max_set_occupancy {
+            self.max_set_occupancy = other.max_set_occupancy;
+            self.most_pressured_set = other.most_pressured_set;
+        }
+        // which sets each used is lost, so this is only a lower bound
+        self.sets_used = self.sets_used.max(other.sets_used);
+        for (mine, theirs) in self
+            .evictions_per_set
+            .iter_mut()
+            .zip(&other.evictions_per_set)
+        {
+            *mine += theirs;
+        }
+        for (mine, theirs) in self.hits_per_set.iter_mut().zip(&other.hits_per_set) {
+            *mine += theirs;
+        }
+        for (mine, theirs) in self.misses_per_set.iter_mut().zip(&other.misses_per_set) {
+            *mine += theirs;
+        }
+        self.writebacks = add(self.writebacks, other.writebacks);
+        self.memory_writes = add(self.memory_writes, other.memory_writes);
+        self.approximate |= other.approximate;
+        self.skipped_accesses = self
+            .skipped_accesses
+            .zip(other.skipped_accesses)
+            .map(|(a, b)| a + b);
+        for &(id, theirs) in &other.streams {
+            match self.streams.binary_search_by_key(&id, |&(id, _)| id) {
+                Ok(i) => {
+                    let mine = &mut self.streams[i].1;
+                    mine.hits += theirs.hits;
+                    mine.misses += theirs.misses;
+                }
+                Err(i) => self.streams.insert(i, (id, theirs)),
+            }
+        }
+        self.miss_word_offsets = self
+            .miss_word_offsets
+            .zip(other.miss_word_offsets)
+            .map(|(a, b)| a + b);
+        self.oracle = self
+            .oracle
+            .zip(other.oracle)
+            .map(|((h, m), (oh, om))| (h + oh, m + om));
+        ThrasheReport {
+            timeline: None,
+            mshr_stats: None,
+            eviction_ages: None,
+            temporal: None,
+            events: None,
+            mean_hit_reuse_distance: None,
+            reuse_distances: None,
+            block_frequencies: None,
+            phases: Vec::new(),
+            first_saturation: None,
+            ..self
+        }
+    }
+
+    /// subtracts the counters of `earlier`, a report of the same cache
+    fn since(mut self, earlier: &ThrasheReport) -> ThrasheReport {
+        let sub = |a: Option<u64>, b: Option<u64>| a.zip(b).map(|(a, b)| a.wrapping_sub(b));
+        let sub_each = |now: &mut [u64], then: &[u64]| {
+            for (now, then) in now.iter_mut().zip(then) {
+                *now = now.wrapping_sub(*then);
+            }
+        };
+
+        self.access_count = self.access_count.wrapping_sub(earlier.access_count);
+        self.hits = self.hits.wrapping_sub(earlier.hits);
+        self.misses = self.misses.wrapping_sub(earlier.misses);
+        self.streaming_accesses = self
+            .streaming_accesses
+            .wrapping_sub(earlier.streaming_accesses);
+        self.bank_conflicts = self.bank_conflicts.wrapping_sub(earlier.bank_conflicts);
+        self.port_conflicts = self.port_conflicts.wrapping_sub(earlier.port_conflicts);
+        self.filtered = self.filtered.wrapping_sub(earlier.filtered);
+        self.occupancy_integral = self
+            .occupancy_integral
+            .wrapping_sub(earlier.occupancy_integral);
+        sub_each(&mut self.evictions_per_set, &earlier.evictions_per_set);
+        sub_each(&mut self.hits_per_set, &earlier.hits_per_set);
+        sub_each(&mut self.misses_per_set, &earlier.misses_per_set);
+        self.writebacks = sub(self.writebacks, earlier.writebacks);
+        self.memory_writes = sub(self.memory_writes, earlier.memory_writes);
+        for (id, now) in &mut self.streams {
+            if let Ok(i) = earlier.streams.binary_search_by_key(id, |&(id, _)| id) {
+                let then = earlier.streams[i].1;
+                now.hits = now.hits.wrapping_sub(then.hits);
+                now.misses = now.misses.wrapping_sub(then.misses);
+            }
+        }
+        self.miss_word_offsets = self
+            .miss_word_offsets
+            .zip(earlier.miss_word_offsets)
+            .map(|(a, b)| a.wrapping_sub(b));
+        self.oracle = self
+            .oracle
+            .zip(earlier.oracle)
+            .map(|((h, m), (eh, em))| (h.wrapping_sub(eh), m.wrapping_sub(em)));
+        self
+    }
+
+    pub fn access_count(&self) -> u64 {
+        self.access_count
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    /// fraction of accesses that hit, 0 if there weren't any
+    pub fn hit_rate(&self) -> f64 {
+        if self.access_count == 0 {
+            0.0
+        } else {
+            self.hits as f64 / self.access_count as f64
+        }
+    }
+
+    /// rough time spent on the accesses, `hit_latency` for every hit and `miss_latency` for every
+    /// miss
+    ///
+    /// under [`CacheState::with_critical_word_first`] `miss_latency` is how long the first word of
+    /// a line takes, each miss then also waits `word_latency` for every word ahead of its own
+    pub fn estimated_cycles(&self, hit_latency: f64, miss_latency: f64, word_latency: f64) -> f64 {
+        let words_waited = self.miss_word_offsets.unwrap_or(0) as f64;
+        self.hits as f64 * hit_latency
+            + self.misses as f64 * miss_latency
+            + words_waited * word_latency
+    }
+
+    /// digest of the access, hit and miss counts and the misses of every set, to assert on a
+    /// whole run at once
+    ///
+    /// FNV-1a over the counters as 64 bit little endian bytes, so it's the same on every platform
+    /// for the same results
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        let counters = [self.access_count, self.hits, self.misses];
+        for counter in counters.iter().chain(&self.misses_per_set) {
+            for byte in counter.to_le_bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
+    }
+
+    /// checks the invariants every report of a [`CacheState`] holds, a failure means a bug or a
+    /// provider or tool putting together reports of its own wrong
+    ///
+    /// that misses are at least the compulsory ones can't be checked here without the trace, see
+    /// [`crate::analysis::compulsory_floor`]
+    pub fn sanity_check(&self) -> Result<(), SanityError> {
+        if self.hits.checked_add(self.misses) != Some(self.access_count) {
+            return Err(SanityError::Unaccounted {
+                access_count: self.access_count,
+                hits: self.hits,
+                misses: self.misses,
+            });
+        }
+        for (counter, total, per_set) in [
+            ("hits", self.hits, &self.hits_per_set),
+            ("misses", self.misses, &self.misses_per_set),
+        ] {
+            let per_set = per_set.iter().sum::<u64>();
+            if per_set != total {
+                return Err(SanityError::SetTotals {
+                    counter,
+                    total,
+                    per_set,
+                });
+            }
+        }
+        let capacity = self.spec.capacity_lines();
+        if self.occupancy > capacity {
+            return Err(SanityError::OverCapacity {
+                occupancy: self.occupancy,
+                capacity,
+            });
+        }
+        let ways = self.spec.lines_per_set();
+        if self.max_set_occupancy > ways {
+            return Err(SanityError::OverfullSet {
+                set: self.most_pressured_set,
+                occupancy: self.max_set_occupancy,
+                ways,
+            });
+        }
+        Ok(())
+    }
+
+    /// the access, counted from 1 when the cache was created, whose fill made every line valid,
+    /// i.e. where the cold phase ended, `None` if the cache never filled up
+    ///
+    /// dropped by [`ThrasheReport::merge`], and [`CacheState::reset_stats`] doesn't clear it
+    pub fn first_saturation_access(&self) -> Option<u64> {
+        self.first_saturation
+    }
+
+    /// the share of the cache's lines that were valid, averaged over every access of the run, 0
+    /// if there weren't any
+    ///
+    /// unlike [`ThrasheReport::occupancy`] this counts the time it took to fill up, so a cache
+    /// well below 1 for a whole run would likely do as well smaller
+    pub fn utilization(&self) -> f64 {
+        if self.access_count == 0 {
+            return 0.0;
+        }
+        let capacity = self.spec.capacity_lines() as f64;
+        self.occupancy_integral as f64 / (self.access_count as f64 * capacity)
+    }
+
+    /// misses per thousand accesses, 0 if there weren't any
+    pub fn mpki(&self) -> f64 {
+        if self.access_count == 0 {
+            0.0
+        } else {
+            self.misses as f64 * 1000.0 / self.access_count as f64
+        }
+    }
+
+    /// non-temporal accesses, these aren't included in [`ThrasheReport::access_count`]
+    pub fn streaming_accesses(&self) -> u64 {
+        self.streaming_accesses
+    }
+
+    /// pairs passed to [`CacheState::touch_pair`] that needed the same bank
+    pub fn bank_conflicts(&self) -> u64 {
+        self.bank_conflicts
+    }
+
+    /// accesses that stalled because [`CacheState::touch_cycle`] was given more than there are
+    /// ports
+    pub fn port_conflicts(&self) -> u64 {
+        self.port_conflicts
+    }
+
+    /// accesses left out by [`CacheState::set_address_filter`], not part of any other count
+    pub fn filtered_accesses(&self) -> u64 {
+        self.filtered
+    }
+
+    /// valid lines at the time of the report
+    pub fn occupancy(&self) -> usize {
+        self.occupancy
+    }
+
+    /// the most lines that were valid at once in any single set
+    pub fn max_set_occupancy(&self) -> usize {
+        self.max_set_occupancy
+    }
+
+    /// the most ways of a set that were in use at once, if this stays below the associativity
+    /// the extra ways were wasted on this workload
+    pub fn max_ways_used(&self) -> usize {
+        self.max_set_occupancy
+    }
+
+    /// sets that ever held a valid line
+    pub fn sets_used(&self) -> usize {
+        self.sets_used
+    }
+
+    /// the first set to reach [`ThrasheReport::max_set_occupancy`], `None` if nothing was ever cached
+    pub fn most_pressured_set(&self) -> Option<usize> {
+        (self.max_set_occupancy > 0).then_some(self.most_pressured_set)
+    }
+
+    /// valid lines replaced in each set, unlike misses this leaves out cold fills of empty ways
+    pub fn evictions_per_set(&self) -> &[u64] {
+        &self.evictions_per_set
+    }
+
+    pub fn hits_per_set(&self) -> &[u64] {
+        &self.hits_per_set
+    }
+
+    pub fn misses_per_set(&self) -> &[u64] {
+        &self.misses_per_set
+    }
+
+    /// coefficient of variation of [`ThrasheReport::misses_per_set`], 0 when misses are spread
+    /// evenly over every set and growing as they pile up in a few
+    pub fn set_balance(&self) -> f64 {
+        let n = self.misses_per_set.len() as f64;
+        let mean = self.misses_per_set.iter().sum::<u64>() as f64 / n;
+        if mean == 0.0 {
+            return 0.0;
+        }
+        let variance = self
+            .misses_per_set
+            .iter()
+            .map(|&m| (m as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        variance.sqrt() / mean
+    }
+
+    /// for each index bit, lowest first, the share of accesses that went to a set with it set,
+    /// a bit stuck at 0 or 1 means the workload only ever uses half the sets
+    pub fn index_bit_usage(&self) -> Vec<f64> {
+        let bits = self.spec.set_num().trailing_zeros();
+        let mut set = vec![0u64; bits as usize];
+        let mut total = 0;
+        for (index, (&hits, &misses)) in self
+            .hits_per_set
+            .iter()
+            .zip(&self.misses_per_set)
+            .enumerate()
+        {
+            let accesses = hits + misses;
+            total += accesses;
+            for (bit, count) in set.iter_mut().enumerate() {
+                if index >> bit & 1 == 1 {
+                    *count += accesses;
+                }
+            }
+        }
+        set.into_iter()
+            .map(|count| {
+                if total == 0 {
+                    0.0
+                } else {
+                    count as f64 / total as f64
+                }
+            })
+            .collect()
+    }
+
+    pub fn spec(&self) -> &CacheSpec {
+        &self.spec
+    }
+
+    /// bytes fetched from memory, every miss brings in a whole block
+    pub fn read_traffic_bytes(&self) -> u64 {
+        self.misses * self.spec.block_size() as u64
+    }
+
+    /// dirty blocks written back to memory, only present under [`WritePolicy::WriteBack`]
+    pub fn writebacks(&self) -> Option<u64> {
+        self.writebacks
+    }
+
+    /// writes passed through to memory, only present under [`WritePolicy::WriteThrough`]
+    pub fn memory_writes(&self) -> Option<u64> {
+        self.memory_writes
+    }
+
+    /// bytes written back to memory by dirty evictions, only present under [`WritePolicy::WriteBack`]
+    pub fn writeback_traffic_bytes(&self) -> Option<u64> {
+        self.writebacks.map(|w| w * self.spec.block_size() as u64)
+    }
+
+    /// only present if [`CacheState::enable_lifetime_tracking`] was called
+    pub fn eviction_ages(&self) -> Option<&LifetimeHistogram> {
+        self.eviction_ages.as_ref()
+    }
+
+    /// only present if the cache was created [`CacheState::with_timestamps`]
+    pub fn temporal(&self) -> Option<&TemporalStats> {
+        self.temporal.as_ref()
+    }
+
+    /// hits of the fully associative cache run [`CacheState::with_fa_oracle`]
+    pub fn oracle_hits(&self) -> Option<u64> {
+        self.oracle.map(|(hits, _)| hits)
+    }
+
+    pub fn oracle_misses(&self) -> Option<u64> {
+        self.oracle.map(|(_, misses)| misses)
+    }
+
+    /// mean number of distinct blocks touched between a hit and the previous access to its block,
+    /// if the cache was created [`CacheState::with_reuse_distances`] and hit at least once
+    pub fn mean_hit_reuse_distance(&self) -> Option<f64> {
+        self.mean_hit_reuse_distance
+    }
+
+    /// approximate `p` percentile, 0 to 1, of the reuse distances of a cache created
+    /// [`CacheState::with_reuse_distances`], see [`ReuseDistanceSketch`] for how far off it can be
+    pub fn reuse_distance_percentile(&self, p: f64) -> Option<u64> {
+        self.reuse_distances.as_ref()?.percentile(p)
+    }
+
+    /// hits and misses of every stream passed to [`CacheState::touch_address_stream`], by stream id
+    pub fn per_stream_stats(&self) -> &[(u16, BatchOutcome)] {
+        &self.streams
+    }
+
+    /// the stats of every phase marked with [`CacheState::mark_phase`] in order, accesses before
+    /// the first marker aren't in any phase
+    pub fn phases(&self) -> &[PhaseStats] {
+        &self.phases
+    }
+
+    /// whether a tracker had to leave blocks out to stay within its
+    /// [`AnalysisBudget`](crate::analysis::AnalysisBudget), making block frequencies, reuse
+    /// distances and temporal stats approximate
+    pub fn approximate(&self) -> bool {
+        self.approximate
+    }
+
+    /// accesses the provider dropped rather than recording, only present for lossy ones like
+    /// [`SamplingCache`](crate::provider::SamplingCache), so the counts here under-count
+    /// by this much
+    pub fn skipped_accesses(&self) -> Option<u64> {
+        self.skipped_accesses
+    }
+
+    pub(crate) fn with_skipped_accesses(mut self, skipped: u64) -> ThrasheReport {
+        self.skipped_accesses = Some(skipped);
+        self
+    }
+
+    /// the events kept by a cache created [`CacheState::with_event_buffer`], oldest first
+    pub fn recent_events(&self) -> Option<&[CacheEvent]> {
+        self.events.as_ref().map(|(events, _)| events.as_slice())
+    }
+
+    /// events pushed out of the buffer by newer ones
+    pub fn dropped_events(&self) -> Option<u64> {
+        self.events.as_ref().map(|&(_, dropped)| dropped)
+    }
+
+    /// the `n` most accessed blocks as `(block aligned address, accesses)`, most accessed first
+    ///
+    /// only present if [`CacheState::enable_frequency_tracking`] was called
+    pub fn top_tags(&self, n: usize) -> Option<&[(u64, u64)]> {
+        self.block_frequencies
+            .as_ref()
+            .map(|blocks| &blocks[..n.min(blocks.len())])
+    }
+
+    /// only present if the cache was created [`CacheState::with_timeline`]
+    pub fn timeline(&self) -> Option<&Timeline> {
+        self.timeline.as_ref()
+    }
+
+    /// only present if the cache was created [`CacheState::with_mshrs`]
+    pub fn mshr_stats(&self) -> Option<&MshrStats> {
+        self.mshr_stats.as_ref()
+    }
+
+    /// timeline as a json array, downsampled to at most `max_points` if given
+    #[cfg(feature = "serde")]
+    pub fn timeline_json(&self, max_points: Option<usize>) -> Option<String> {
+        let timeline = self.timeline.as_ref()?;
+        match max_points {
+            Some(max_points) => Some(timeline.downsample(max_points).to_json()),
+            None => Some(timeline.to_json()),
+        }
+    }
+
+    /// the counters in the prometheus text format, every metric name starting with `prefix`
+    #[cfg(feature = "metrics")]
+    pub fn to_prometheus(&self, prefix: &str) -> String {
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, samples: &[(String, f64)]| {
+            writeln!(out, "# HELP {prefix}_{name} {help}").unwrap();
+            writeln!(out, "# TYPE {prefix}_{name} gauge").unwrap();
+            for (labels, value) in samples {
+                writeln!(out, "{prefix}_{name}{labels} {value}").unwrap();
+            }
+        };
+        let single = |value: f64| [(String::new(), value)];
+
+        gauge(
+            "accesses",
+            "Cache accesses recorded",
+            &single(self.access_count as f64),
+        );
+        gauge("hits", "Accesses that hit", &single(self.hits as f64));
+        gauge(
+            "misses",
+            "Accesses that missed",
+            &single(self.misses as f64),
+        );
+        gauge(
+            "hit_rate",
+            "Fraction of accesses that hit",
+            &single(self.hit_rate()),
+        );
+        let per_set: Vec<_> = self
+            .misses_per_set
+            .iter()
+            .enumerate()
+            .map(|(set, &misses)| (format!("{{set=\"{set}\"}}"), misses as f64))
+            .collect();
+        gauge("set_misses", "Accesses that missed in each set", &per_set);
+        out
+    }
+}
+
+/// Where a [`Thrashe`] value is considered to live for cache mapping
+pub trait AddressSource {
+    /// `real` is the address of the wrapper itself
+    fn address(&self, real: u64) -> u64;
+}
+
+/// Maps values by where they actually are in memory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RealAddress;
+
+impl AddressSource for RealAddress {
+    fn address(&self, real: u64) -> u64 {
+        real
+    }
+}
+
+/// A fixed address, independent of the allocator and ASLR, for reproducible experiments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualAddress(pub u64);
+
+impl AddressSource for VirtualAddress {
+    fn address(&self, _real: u64) -> u64 {
+        self.0
+    }
+}
+
+/// Wrapper type that records dereferences in a cache emulation
+pub struct Thrashe<T, C: CacheProvider = GlobalCache, A: AddressSource = RealAddress> {
+    inner: T,
+    address: A,
+    /// only names the provider, so `Thrashe` is `Send`/`Sync` whenever `T` and `A` are whatever `C` is
+    _marker: PhantomData<fn() -> C>,
+}
+
+const _SAME_SIZE: () = assert!(size_of::<usize>() == size_of::<Thrashe<usize>>());
+
+impl<T> Thrashe<T> {
+    pub fn new(value: T) -> Self {
+        Thrashe {
+            inner: value,
+            address: RealAddress,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Thrashe<T, GlobalCache, VirtualAddress> {
+    /// accesses are mapped as if the value lived at `address`, wherever it really is
+    pub fn with_address(value: T, address: u64) -> Self {
+        Thrashe::with_address_in(value, address)
+    }
+}
+
+impl<T, C: CacheProvider> Thrashe<T, C> {
+    /// wraps a value recording into a provider other than [`GlobalCache`], e.g. `Thrashe::<_, MyCache>::new_in(value)`
+    pub fn new_in(value: T) -> Self {
+        Thrashe {
+            inner: value,
+            address: RealAddress,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, C: CacheProvider> Thrashe<T, C, VirtualAddress> {
+    /// [`Thrashe::with_address`] for a provider other than [`GlobalCache`]
+    pub fn with_address_in(value: T, address: u64) -> Self {
+        Thrashe {
+            inner: value,
+            address: VirtualAddress(address),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, C: CacheProvider, A: AddressSource> Thrashe<T, C, A> {
+    /// the address accesses to this value are mapped by
+    pub fn address(value: &Self) -> u64 {
+        value
+            .address
+            .address((value as *const Self) as usize as u64)
+    }
+
+    pub fn prefetch(value: &Self) {
+        let address = Self::address(value);
+        C::with_cache(|state| state.touch_address(address));
+    }
+
+    /// whether the two values overlap a common block of `C`'s cache, so touching one brings the
+    /// other in too, false when `C` has no cache configured
+    pub fn shares_block_with<U, B: AddressSource>(value: &Self, other: &Thrashe<U, C, B>) -> bool {
+        let Some(state) = &*C::read_cache() else {
+            return false;
+        };
+        let blocks = |address: u64, size: usize| {
+            let bits = state.spec().block_size_bits();
+            (address >> bits)..=((address + size.max(1) as u64 - 1) >> bits)
+        };
+        let (mine, theirs) = (
+            blocks(Self::address(value), size_of::<T>()),
+            blocks(Thrashe::address(other), size_of::<U>()),
+        );
+        mine.start() <= theirs.end() && theirs.start() <= mine.end()
+    }
+
+    /// software prefetch of the `distance` addresses `stride_bytes` apart following this value,
+    /// e.g. the next elements of an array being walked
+    pub fn prefetch_ahead(value: &Self, stride_bytes: isize, distance: usize) {
+        let address = Self::address(value);
+        C::with_cache(|state| {
+            for i in 1..=distance as i64 {
+                state.touch_address(address.wrapping_add_signed(stride_bytes as i64 * i));
+            }
+        });
+    }
+}
+
+impl<T: Deref, C: CacheProvider, A: AddressSource> Thrashe<T, C, A> {
+    /// touches `len` bytes from the value `inner` points to, e.g. a whole boxed buffer rather than
+    /// just the pointer, returning how those blocks fared or `None` if `C` isn't configured
+    ///
+    /// the range always starts at the pointee's real address, whatever the address source is
+    pub fn touch_bytes(value: &Self, len: usize) -> Option<BatchOutcome> {
+        let address = (&*value.inner as *const T::Target).cast::<u8>() as usize as u64;
+        C::with_cache(|state| state.touch_range(address, len as u64))
+    }
+
+    /// reads through a smart pointer like an `Arc` or `Box`, touching the pointee where the data
+    /// lives rather than the wrapper holding the pointer like dereferencing would
+    pub fn deref_pointee(value: &Self) -> &T::Target {
+        let pointee = &*value.inner;
+        let address = (pointee as *const T::Target).cast::<u8>() as usize as u64;
+        C::with_cache(|state| state.touch_address(address));
+        pointee
+    }
+}
+
+impl<T, C: CacheProvider, A: AddressSource> Thrashe<Option<T>, C, A> {
+    /// reads an option the way the generated code does, touching its discriminant then, if it's
+    /// `Some`, the payload, rather than the single access dereferencing makes
+    ///
+    /// when the layout stores the discriminant in a niche of the payload, as for `Option<Box<_>>`
+    /// or `Option<&_>`, the two are the same read and only the payload is touched, and a `None`
+    /// is always taken to keep its discriminant where the option starts
+    pub fn deref_option(value: &Self) -> Option<&T> {
+        let address = Self::address(value);
+        let option = (&value.inner as *const Option<T>) as usize as u64;
+        C::with_cache(|state| match &value.inner {
+            Some(payload) => {
+                let offset = (payload as *const T) as usize as u64 - option;
+                if size_of::<Option<T>>() != size_of::<T>() {
+                    // the payload is either after the discriminant or, when it's the more
+                    // aligned of the two, before it
+                    let tag = if offset > 0 { 0 } else { size_of::<T>() as u64 };
+                    state.touch_address(address + tag);
+                }
+                state.touch_address(address + offset);
+            }
+            None => {
+                state.touch_address(address);
+            }
+        });
+        value.inner.as_ref()
+    }
+}
+
+/// cloning reads `inner` directly so it doesn't count as an access, only dereferencing the clone does
+impl<T: Clone, C: CacheProvider, A: AddressSource + Clone> Clone for Thrashe<T, C, A> {
+    fn clone(&self) -> Self {
+        Thrashe {
+            inner: self.inner.clone(),
+            address: self.address.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Copy, C: CacheProvider, A: AddressSource + Copy> Copy for Thrashe<T, C, A> {}
+
+impl<T, C: CacheProvider, A: AddressSource> Deref for Thrashe<T, C, A> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        let address = Self::address(self);
+        C::with_cache(|state| state.touch_address(address));
+
+        &self.inner
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    crate::new_provider!(VirtualCache);
+    crate::new_provider!(CopyCache);
+    crate::new_provider!(PrefetchCache);
+    crate::new_provider!(TouchBytesCache);
+    crate::new_provider!(AliasCache);
+    crate::new_provider!(PointeeCache);
+    crate::new_provider!(OptionCache);
+
+    #[test]
+    fn basic() {
+        GlobalCache::configure(CacheSpec::spec_8kib_32bit_2way());
+        let foo = Thrashe::new(42);
+
+        let _ = *foo;
+        let _ = *foo;
+
+        let report = GlobalCache::finish().unwrap();
+        println!("{:?}", report);
+        assert_eq!(report.hits, 1);
+        assert_eq!(report.misses, 1);
+    }
+
+    #[test]
+    fn const_spec() {
+        const SPEC: CacheSpec = CacheSpec::new(6, 6, 3).with_policy(ReplacementPolicy::Bip);
+        const SIZE: u64 = SPEC.size();
+        static PRESET: CacheSpec = CacheSpec::spec_8kib_32bit_2way();
+        const _: () = assert!(SIZE == 32 * 1024);
+
+        assert_eq!(SPEC.set_num(), 64);
+        assert_eq!(PRESET.size(), 8192);
+    }
+
+    #[test]
+    fn address_fields() {
+        let layout = CacheSpec::spec_8kib_32bit_2way().bit_layout(32);
+        assert_eq!(
+            layout,
+            BitLayout {
+                tag_bits: 20,
+                index_bits: 7,
+                offset_bits: 5,
+            }
+        );
+        assert_eq!(
+            layout.to_string(),
+            "tag [31:12] 20 bits | index [11:5] 7 bits | offset [4:0] 5 bits"
+        );
+        assert_eq!(
+            CacheSpec::with_ways(6, 0, 16).bit_layout(48).to_string(),
+            "tag [47:6] 42 bits | offset [5:0] 6 bits"
+        );
+    }
+
+    #[test]
+    fn vipt_constraint() {
+        // the classic 4KiB page L1: size / ways can't exceed the page
+        let l1 = CacheSpec::spec_8kib_32bit_2way().require_vipt(12).unwrap();
+        assert_eq!(l1, CacheSpec::spec_8kib_32bit_2way());
+        let err = CacheSpec::new(5, 8, 1).require_vipt(12).unwrap_err();
+        assert_eq!(
+            err,
+            ViptError {
+                index_bits: 13,
+                page_bits: 12,
+            }
+        );
+        // doubling the ways instead keeps the index inside the page
+        assert!(CacheSpec::new(5, 7, 2).require_vipt(12).is_ok());
+    }
+
+    #[test]
+    fn spec_getters() {
+        let spec = CacheSpec::spec_8kib_32bit_2way();
+        assert_eq!(spec.block_size(), 32);
+        assert_eq!(spec.block_size_bits(), 5);
+        assert_eq!(spec.set_num(), 128);
+        assert_eq!(spec.lines_per_set(), 2);
+        assert_eq!(spec.associativity(), 2);
+        assert_eq!(spec.capacity_lines(), 256);
+        assert_eq!(spec.size(), 8192);
+    }
+
+    #[test]
+    fn three_way() {
+        let spec = CacheSpec::with_ways(5, 7, 3);
+        assert_eq!(spec.associativity(), 3);
+        assert_eq!(spec.size(), 32 * 128 * 3);
+
+        let cache = CacheState::from_spec(spec);
+        assert_eq!(cache.sets.len(), 128);
+        assert!(cache.sets.iter().all(|set| set.len() == 3));
+
+        // three blocks in one set all fit, a fourth evicts the least recently used
+        let stride = 4096;
+        cache.replay(&[0, stride, 2 * stride, 0, stride, 2 * stride]);
+        assert_eq!(cache.make_report().hits, 3);
+        cache.replay(&[3 * stride, stride, 2 * stride, 0]);
+        let report = cache.make_report();
+        assert_eq!(report.hits, 5);
+        assert_eq!(report.misses, 5);
+    }
+
+    #[test]
+    fn four_way_lru() {
+        let cache = CacheState::from_spec(CacheSpec::new(5, 0, 2));
+        let (a, b, c, d, e) = (0, 32, 64, 96, 128);
+        cache.replay(&[a, b, c, d, d, b, a]);
+        // c is the least recently used, though b is older than a in way 0
+        cache.replay(&[e, b, d, a]);
+        let report = cache.make_report();
+        assert_eq!(report.hits, 6);
+        assert_eq!(report.misses, 5);
+    }
+
+    #[test]
+    fn virtual_addresses() {
+        // direct mapped, so two blocks in the same set always evict each other
+        VirtualCache::configure(CacheSpec::new(5, 7, 0));
+        let a = Thrashe::<_, VirtualCache, _>::with_address_in(1u8, 4096);
+        let b = Thrashe::<_, VirtualCache, _>::with_address_in(2u8, 2 * 4096);
+        let a_again = Thrashe::<_, VirtualCache, _>::with_address_in(3u8, 4096 + 8);
+        assert_eq!(Thrashe::address(&a), 4096);
+
+        for _ in 0..4 {
+            let _ = *a;
+            let _ = *b;
+        }
+        // a different value pinned to the same block shares its line
+        let _ = *a;
+        let _ = *a_again;
+
+        let report = VirtualCache::finish().unwrap();
+        assert_eq!(report.misses, 9);
+        assert_eq!(report.hits, 1);
+    }
+
+    /// a provider that couldn't be shared itself, which `Thrashe` never holds a value of
+    #[allow(dead_code)]
+    struct RcProvider(std::rc::Rc<()>);
+
+    impl CacheProvider for RcProvider {
+        fn get_cache() -> &'static std::sync::RwLock<Option<CacheState>> {
+            static STATE: std::sync::RwLock<Option<CacheState>> = std::sync::RwLock::new(None);
+            &STATE
+        }
+    }
+
+    #[test]
+    fn shareable_across_threads() {
+        // even through a provider that isn't `Sync` itself
+        const { crate::provider::assert_provider::<RcProvider>() };
+
+        let value = Thrashe::<_, RcProvider>::new_in(3u64);
+        std::thread::scope(|s| {
+            s.spawn(|| assert_eq!(Thrashe::address(&value), &value as *const _ as u64));
+        });
+    }
+
+    #[test]
+    fn copies_are_free() {
+        CopyCache::configure(CacheSpec::spec_8kib_32bit_2way());
+        let value = Thrashe::<u64, CopyCache>::new_in(7);
+        let mut sum = 0;
+        for _ in 0..10 {
+            let copy = value;
+            sum += *copy;
+        }
+        assert_eq!(sum, 70);
+
+        // only the ten derefs were accesses, not the copies
+        let report = CopyCache::finish().unwrap();
+        assert_eq!(report.access_count(), 10);
+    }
+
+    #[test]
+    fn software_prefetch() {
+        PrefetchCache::configure(CacheSpec::spec_8kib_32bit_2way());
+        let elements: Vec<_> = (0..16)
+            .map(|i| Thrashe::<_, PrefetchCache, _>::with_address_in(i, 8192 + 32 * i))
+            .collect();
+
+        // every fourth element prefetches the blocks of the next four
+        let mut sum = 0;
+        for (i, element) in elements.iter().enumerate() {
+            if i % 4 == 0 {
+                Thrashe::prefetch_ahead(element, 32, 4);
+            }
+            sum += **element;
+        }
+        assert_eq!(sum, (0..16).sum::<u64>());
+
+        let report = PrefetchCache::finish().unwrap();
+        // only the first element and the sixteen prefetched blocks went to memory
+        assert_eq!(report.misses(), 1 + 16);
+        assert_eq!(report.hits(), 15);
+    }
+
+    #[test]
+    fn block_aliasing() {
+        let small = Thrashe::<_, AliasCache, _>::with_address_in(0u32, 24);
+        let neighbour = Thrashe::<_, AliasCache, _>::with_address_in(0u64, 16);
+        let far = Thrashe::<_, AliasCache, _>::with_address_in(0u8, 64);
+        // no spec, no blocks
+        assert!(!Thrashe::shares_block_with(&small, &neighbour));
+
+        AliasCache::configure(CacheSpec::spec_8kib_32bit_2way());
+        assert!(Thrashe::shares_block_with(&small, &neighbour));
+        assert!(!Thrashe::shares_block_with(&small, &far));
+        // spilling over into the next block is enough
+        let straddling = Thrashe::<_, AliasCache, _>::with_address_in([0u8; 16], 56);
+        assert!(Thrashe::shares_block_with(&straddling, &far));
+        // and asking isn't an access
+        assert_eq!(AliasCache::finish().unwrap().access_count(), 0);
+    }
+
+    #[test]
+    fn shared_pointee() {
+        PointeeCache::configure(CacheSpec::spec_8kib_32bit_2way());
+        let shared = Thrashe::<_, PointeeCache>::new_in(std::sync::Arc::new([7u64; 128]));
+        let data = Thrashe::deref_pointee(&shared);
+        assert_eq!(data[0], 7);
+
+        let data_address = data.as_ptr() as u64;
+        PointeeCache::with_cache(|state| {
+            assert!(state.contains(data_address));
+            assert!(!state.contains(Thrashe::address(&shared)));
+        });
+        assert_eq!(PointeeCache::finish().unwrap().access_count(), 1);
+    }
+
+    #[test]
+    fn option_discriminant() {
+        OptionCache::configure(CacheSpec::spec_8kib_32bit_2way());
+        // the discriminant ends the first block and the payload starts the next
+        let some = Thrashe::<_, OptionCache, _>::with_address_in(Some([3u64; 64]), 24);
+        assert_eq!(Thrashe::deref_option(&some).map(|a| a[63]), Some(3));
+        let report = OptionCache::get_report().unwrap();
+        assert_eq!(report.access_count(), 2);
+        assert_eq!(report.misses(), 2);
+        OptionCache::with_cache(|state| assert!(state.contains(32)));
+
+        let none = Thrashe::<Option<[u64; 64]>, OptionCache, _>::with_address_in(None, 24);
+        assert_eq!(Thrashe::deref_option(&none), None);
+        assert_eq!(OptionCache::get_report().unwrap().access_count(), 3);
+
+        // the niche in the box's pointer is the discriminant
+        let niche = Thrashe::<_, OptionCache, _>::with_address_in(Some(Box::new(5u64)), 4096);
+        assert_eq!(Thrashe::deref_option(&niche).map(|b| **b), Some(5));
+        assert_eq!(OptionCache::finish().unwrap().access_count(), 4);
+    }
+
+    #[test]
+    fn boxed_bytes() {
+        TouchBytesCache::configure(CacheSpec::spec_8kib_32bit_2way());
+        let buffer = Thrashe::<_, TouchBytesCache>::new_in(Box::new([0u8; 4096]));
+        let start = buffer.as_ptr() as u64;
+        // the deref above was one access to the wrapper itself
+        let before = TouchBytesCache::get_report().unwrap().access_count();
+
+        let outcome = Thrashe::touch_bytes(&buffer, 4096).unwrap();
+        let blocks = if start.is_multiple_of(32) { 128 } else { 129 };
+        assert_eq!(outcome.accesses(), blocks);
+        assert_eq!(outcome.misses, blocks);
+        let report = TouchBytesCache::finish().unwrap();
+        assert_eq!(report.access_count(), before + blocks);
+    }
+
+    #[test]
+    fn batch_deltas() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        let first = cache.touch_iter((0..64).map(|i| 4096 + 8 * i));
+        let before = cache.make_report();
+        assert_eq!(
+            first,
+            BatchOutcome {
+                hits: 48,
+                misses: 16
+            }
+        );
+
+        // second pass over half the region plus some new blocks
+        let second = cache.touch_iter((32..96).map(|i| 4096 + 8 * i));
+        let after = cache.make_report();
+        assert_eq!(second.accesses(), 64);
+        assert_eq!(second.hits, after.hits - before.hits);
+        assert_eq!(second.misses, after.misses - before.misses);
+        assert_eq!(
+            second,
+            BatchOutcome {
+                hits: 56,
+                misses: 8
+            }
+        );
+    }
+
+    #[test]
+    fn stride_conflicts() {
+        let spec = CacheSpec::spec_8kib_32bit_2way();
+        assert_eq!(spec.conflict_stride(), 4096);
+        assert_eq!(
+            spec.index_of(4200),
+            spec.index_of(4200 + spec.conflict_stride())
+        );
+        assert_ne!(
+            spec.index_of(4200),
+            spec.index_of(4200 + spec.conflict_stride() / 2)
+        );
+        assert_ne!(
+            spec.split(4200).1,
+            spec.split(4200 + spec.conflict_stride()).1
+        );
+    }
+
+    #[test]
+    fn hottest_blocks() {
+        let mut cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        cache.enable_frequency_tracking();
+
+        for i in 0..100 {
+            cache.touch_address(8192 + 4);
+            cache.touch_address(32 * (i % 10));
+        }
+
+        let report = cache.make_report();
+        let top = report.top_tags(3).unwrap();
+        assert_eq!(top[0], (8192, 100));
+        assert_eq!(top[1], (0, 10));
+        assert_eq!(top.len(), 3);
+        assert_eq!(report.top_tags(100).unwrap().len(), 11);
+    }
+
+    #[test]
+    fn nontemporal_bypasses() {
+        let normal = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        let streaming = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        let stride = 4096;
+
+        // a 2 way set holding two hot blocks while a stream runs through the same set
+        for i in 0..32 {
+            normal.touch_address(0);
+            normal.touch_address(stride);
+            streaming.touch_address(0);
+            streaming.touch_address(stride);
+            streaming.touch_address_nontemporal((i + 2) * stride);
+        }
+
+        let (normal, streaming_report) = (normal.make_report(), streaming.make_report());
+        assert_eq!(streaming_report.streaming_accesses(), 32);
+        assert_eq!(streaming_report.access_count, normal.access_count);
+        assert_eq!(streaming_report.hits, normal.hits);
+        assert_eq!(streaming_report.misses, 2);
+        assert!(streaming.contains(0) && streaming.contains(stride));
+        assert!(!streaming.contains(2 * stride));
+    }
+
+    #[test]
+    fn reports_compare() {
+        let run = || {
+            let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).with_timeline(16);
+            cache.replay(&crate::analysis::conflict_set(&cache.spec, 4200, 3));
+            cache.touch_iter((0..64).map(|i| 8 * i));
+            cache.make_report()
+        };
+
+        let first = run();
+        assert_eq!(first, run());
+        assert_eq!(first.clone(), first);
+        assert_ne!(
+            first,
+            CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).make_report()
+        );
+    }
+
+    #[test]
+    fn near_misses() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).with_timestamps(10);
+        let [a, b, c] = [0, 4096, 8192];
+
+        cache.touch_address_at(a, 0);
+        cache.touch_address_at(b, 10);
+        // evicts a, which comes straight back
+        cache.touch_address_at(c, 20);
+        cache.touch_address_at(a, 25);
+        // evicts b, which isn't needed again for a long time
+        cache.touch_address_at(b, 100);
+
+        let report = cache.make_report();
+        let temporal = report.temporal().unwrap();
+        assert_eq!(temporal.reuses, 2);
+        assert_eq!(temporal.mean_inter_access, (25.0 + 90.0) / 2.0);
+        assert_eq!(
+            temporal.near_misses,
+            [crate::temporal::NearMiss {
+                block: a,
+                evicted_at: 20,
+                reused_at: 25,
+            }]
+        );
+    }
+
+    #[test]
+    fn tag_indexed() {
+        let spec = CacheSpec::with_ways(5, 0, 512);
+        let trace: Vec<u64> = crate::patterns::random_uniform(0, 1 << 15, 20_000, 9).collect();
+
+        let linear = CacheState::from_spec(spec.clone());
+        let indexed = CacheState::from_spec(spec).with_tag_index();
+        for (i, &address) in trace.iter().enumerate() {
+            assert_eq!(
+                linear.touch_address(address),
+                indexed.touch_address(address)
+            );
+            if i % 1000 == 0 {
+                assert_eq!(linear.invalidate(address), indexed.invalidate(address));
+            }
+        }
+        // same report either way, the index only changes how hits are found
+        assert_eq!(linear.make_report(), indexed.make_report());
+        assert!(indexed.make_report().hits() > 5000);
+    }
+
+    #[test]
+    fn range_touches() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        assert_eq!(cache.touch_range(30, 40).misses, 3);
+        assert_eq!(
+            cache.touch_range(64, 32),
+            BatchOutcome { hits: 1, misses: 0 }
+        );
+        assert_eq!(cache.touch_range(64, 0).accesses(), 0);
+    }
+
+    #[test]
+    fn replay_until_thrashing() {
+        let spec = CacheSpec::spec_8kib_32bit_2way();
+        let lap: Vec<u64> = (0..64).map(|i| 32 * i).collect();
+        let mut trace = lap.repeat(8);
+        let settled = trace.len();
+        for _ in 0..20 {
+            trace.extend(crate::analysis::conflict_set(&spec, 4096, 3));
+        }
+
+        // the laps only miss the first time round, so the next miss is the start of the thrashing
+        let cache = CacheState::from_spec(spec.clone());
+        assert_eq!(
+            cache.replay_until(&trace, |stats| stats.misses > 64),
+            settled
+        );
+
+        let cache = CacheState::from_spec(spec);
+        assert_eq!(cache.replay_until(&trace, |_| false), trace.len());
+        assert_eq!(cache.make_report().access_count() as usize, trace.len());
+    }
+
+    #[test]
+    fn used_sets_and_ways() {
+        let spec = CacheSpec::new(5, 7, 2);
+        let cache = CacheState::from_spec(spec.clone());
+        // every block lands in its own set
+        cache.touch_iter(crate::patterns::sequential(0, 32, 100));
+        cache.invalidate_set(0);
+
+        let report = cache.make_report();
+        assert_eq!(report.sets_used(), 100);
+        assert_eq!(report.max_ways_used(), 1);
+        assert!(report.max_ways_used() < spec.associativity());
+    }
+
+    #[test]
+    fn address_filter() {
+        let mut filtered = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        filtered.set_address_filter(Box::new(|address| address < 1 << 40));
+        let plain = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+
+        let data: Vec<u64> = (0..64).map(|b| b * 32).collect();
+        let stack: Vec<u64> = (0..512).map(|b| (1 << 47) - b * 32).collect();
+        filtered.replay(&data);
+        filtered.replay(&stack);
+        filtered.replay(&data);
+        plain.replay(&data);
+        plain.replay(&data);
+
+        let (filtered, plain) = (filtered.make_report(), plain.make_report());
+        assert_eq!(filtered.filtered_accesses(), 512);
+        assert_eq!(filtered.access_count(), plain.access_count());
+        assert_eq!(filtered.hits(), plain.hits());
+        assert_eq!(filtered.misses(), plain.misses());
+    }
+
+    #[test]
+    fn interval_reports() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way())
+            .with_write_policy(WritePolicy::WriteThrough);
+        cache.replay(&[0, 0, 32]);
+        let first = cache.report_delta();
+        assert_eq!((first.hits(), first.misses()), (1, 2));
+
+        cache.replay(&[0, 64]);
+        cache.touch_address_write(64);
+        let second = cache.report_delta();
+        assert_eq!(second.access_count(), 3);
+        assert_eq!((second.hits(), second.misses()), (2, 1));
+        assert_eq!(second.hits_per_set()[..3], [1, 0, 1]);
+        assert_eq!(second.memory_writes(), Some(1));
+        // the contents aren't a delta
+        assert_eq!(second.occupancy(), 3);
+
+        assert_eq!(cache.report_delta().access_count(), 0);
+        assert_eq!(cache.make_report().access_count(), 6);
+    }
+
+    #[test]
+    fn critical_word() {
+        let cycles = |offset: u64| {
+            let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way())
+                .with_critical_word_first(3);
+            cache.replay(&[offset, 4096 + offset, 8192 + offset]);
+            cache.make_report().estimated_cycles(1.0, 100.0, 4.0)
+        };
+        // three misses, the last 8 byte word of a 32 byte line is three words in
+        assert_eq!(cycles(0), 300.0);
+        assert_eq!(cycles(24), 300.0 + 3.0 * 3.0 * 4.0);
+
+        // without the mode where in the line a miss lands doesn't matter
+        let plain = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        plain.replay(&[24, 4096 + 24, 8192 + 24]);
+        assert_eq!(plain.make_report().estimated_cycles(1.0, 100.0, 4.0), 300.0);
+    }
+
+    #[test]
+    fn report_fingerprint() {
+        let fingerprint = |trace: &[u64]| {
+            let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+            cache.replay(trace);
+            cache.make_report().fingerprint()
+        };
+        let trace: Vec<u64> = crate::patterns::random_uniform(0, 1 << 16, 1000, 3).collect();
+        assert_eq!(fingerprint(&trace), fingerprint(&trace));
+        assert_ne!(fingerprint(&trace), fingerprint(&trace[1..]));
+        // pinned, so a change in the hash itself shows up too
+        assert_eq!(fingerprint(&[0, 0, 32]), 0x9c04e1a890439b45);
+    }
+
+    #[test]
+    fn stream_attribution() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        // stream 0 rereads a few blocks while stream 1 strides through far more than fits
+        for i in 0..2048 {
+            cache.touch_address_stream((i % 8) * 32, 0);
+            cache.touch_address_stream((1 << 20) + i * 64, 1);
+        }
+
+        let report = cache.make_report();
+        let streams = report.per_stream_stats();
+        assert_eq!(
+            streams.iter().map(|&(id, _)| id).collect::<Vec<_>>(),
+            [0, 1]
+        );
+        let (reuse, scan) = (streams[0].1, streams[1].1);
+        assert_eq!(reuse.accesses() + scan.accesses(), report.access_count());
+        assert_eq!(scan.misses, 2048);
+        assert!(scan.misses > 10 * reuse.misses, "{scan:?} vs {reuse:?}");
+    }
+
+    #[test]
+    fn port_pressure() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way().with_ports(2));
+        for cycle in 0..10 {
+            let base = cycle * 8;
+            let outcome = cache.touch_cycle(&[
+                CacheAccess::read(base),
+                CacheAccess::read(base + 4),
+                CacheAccess::write(4096 + base),
+            ]);
+            assert_eq!(outcome.accesses(), 3);
+        }
+        cache.touch_cycle(&[CacheAccess::read(0), CacheAccess::read(8)]);
+
+        let report = cache.make_report();
+        assert_eq!(report.port_conflicts(), 10);
+        assert_eq!(report.access_count(), 32);
+    }
+
+    #[test]
+    fn way_contents() {
+        let cache = CacheState::from_spec(CacheSpec::new(5, 2, 2));
+        let set_stride = 32 * 4;
+        cache.touch_address(1);
+        cache.touch_address(set_stride + 4);
+        cache.touch_address(0);
+        match cache.ways(0).as_slice() {
+            [Some(first), Some(second), None, None] => {
+                assert_eq!(first.block, 0);
+                assert_eq!(first.last_access, 2);
+                assert_eq!(second.block, set_stride);
+                assert_eq!(second.last_access, 1);
+            }
+            ways => panic!("unexpected ways {ways:?}"),
+        }
+        assert_eq!(cache.ways(1), [None; 4]);
+
+        cache.invalidate(0);
+        assert!(matches!(
+            cache.ways(0).as_slice(),
+            [None, Some(_), None, None]
+        ));
+        assert_eq!(cache.make_report().access_count(), 3);
+    }
+
+    #[test]
+    fn recency_ranks() {
+        let cache = CacheState::from_spec(CacheSpec::new(5, 2, 2));
+        let set_stride = 32 * 4;
+        for i in 0..4 {
+            cache.touch_address(i * set_stride);
+        }
+        cache.touch_address(0);
+        assert_eq!(cache.recency_rank(0), Some(0));
+        assert_eq!(cache.recency_rank(3 * set_stride), Some(1));
+        // the next miss in the set evicts the block ranked last
+        assert_eq!(cache.recency_rank(set_stride), Some(3));
+        assert_eq!(cache.make_report().access_count(), 5);
+        cache.touch_address(4 * set_stride);
+        assert_eq!(cache.recency_rank(set_stride), None);
+        assert_eq!(cache.recency_rank(2 * set_stride), Some(3));
+    }
+
+    #[test]
+    fn lru_across_wrap() {
+        let spec = CacheSpec::new(5, 2, 1);
+        let set_stride = 32 * 4;
+        // the second block is used just before the stamps wrap and the first again just after
+        let cache = CacheState::from_spec(spec.clone());
+        cache.epoch.store((1 << ACCESS_BITS) - 2, Ordering::Relaxed);
+        cache.replay(&[0, set_stride, 0]);
+        assert_eq!(cache.ways(0)[0].unwrap().last_access, 0);
+        assert_eq!(cache.recency_rank(0), Some(0));
+        assert_eq!(cache.recency_rank(set_stride), Some(1));
+        cache.touch_address(2 * set_stride);
+        assert!(cache.contains(0));
+        assert!(!cache.contains(set_stride));
+
+        // a block that went in as least recently used is still the first to go after the wrap
+        let lip = CacheState::from_spec(spec).with_insertion(Insertion::Bip {
+            probability: 0.0,
+            seed: 0,
+        });
+        lip.epoch.store((1 << ACCESS_BITS) - 1, Ordering::Relaxed);
+        lip.replay(&[0, 0, set_stride]);
+        assert_eq!(lip.recency_rank(set_stride), Some(1));
+        lip.touch_address(2 * set_stride);
+        assert!(lip.contains(0));
+        assert!(!lip.contains(set_stride));
+    }
+
+    #[test]
+    fn inclusion_prefill() {
+        let l1 = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        l1.replay(&crate::patterns::random_uniform(0, 1 << 16, 2000, 4).collect::<Vec<_>>());
+        let l2 = CacheState::from_spec(CacheSpec::new(6, 8, 3));
+        l2.prefill_from(&l1);
+
+        let resident: Vec<u64> = (0..1 << 16)
+            .step_by(32)
+            .filter(|&b| l1.contains(b))
+            .collect();
+        assert_eq!(resident.len(), l1.occupancy());
+        assert!(resident.iter().all(|&b| l2.contains(b)));
+        assert_eq!(l2.make_report().access_count(), 0);
+
+        let small = CacheState::from_spec(CacheSpec::new(5, 0, 1));
+        small.prefill_from(&l1);
+        assert_eq!(small.occupancy(), 2);
+    }
+
+    #[test]
+    fn report_invariants() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        cache.replay(&crate::patterns::random_uniform(0, 1 << 16, 5000, 2).collect::<Vec<_>>());
+        let report = cache.make_report();
+        assert_eq!(report.sanity_check(), Ok(()));
+
+        let mut corrupt = report.clone();
+        corrupt.hits += 1;
+        let err = corrupt.sanity_check().unwrap_err();
+        assert!(matches!(err, SanityError::Unaccounted { .. }));
+
+        let mut corrupt = report.clone();
+        corrupt.misses_per_set[3] += 1;
+        assert_eq!(
+            corrupt.sanity_check(),
+            Err(SanityError::SetTotals {
+                counter: "misses",
+                total: report.misses(),
+                per_set: report.misses() + 1,
+            })
+        );
+
+        let mut corrupt = report;
+        corrupt.occupancy = 257;
+        assert_eq!(
+            corrupt.sanity_check().unwrap_err().to_string(),
+            "257 valid lines in a cache of 256"
+        );
+    }
+
+    #[test]
+    fn single_set_reset() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        cache.replay(&[0, 0, 32, 32, 0]);
+        cache.reset_set_stats(0);
+        assert_eq!(cache.make_report().hits_per_set()[..2], [0, 1]);
+
+        cache.replay(&[0, 0]);
+        let report = cache.make_report();
+        assert_eq!(report.hits_per_set()[..2], [2, 1]);
+        assert_eq!(report.misses_per_set()[..2], [0, 1]);
+        // the totals and the contents weren't touched
+        assert_eq!(report.hits(), 5);
+        assert!(cache.contains(0));
+    }
+
+    #[test]
+    fn phase_markers() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        cache.touch_address(0);
+        cache.mark_phase("init");
+        cache.replay(&(0..64).map(|b| b * 32).collect::<Vec<_>>());
+        cache.mark_phase("compute");
+        cache.replay(&[0, 32, 64]);
+
+        let report = cache.make_report();
+        let phases: Vec<_> = report
+            .phases()
+            .iter()
+            .map(|p| (p.name.as_str(), p.access_count, p.hits, p.misses))
+            .collect();
+        assert_eq!(phases, [("init", 64, 1, 63), ("compute", 3, 3, 0)]);
+        assert!(report.merge(&cache.make_report()).phases().is_empty());
+    }
+
+    #[test]
+    fn set_flush() {
+        let spec = CacheSpec::new(5, 7, 2);
+        let cache = CacheState::from_spec(spec.clone());
+        cache.replay(&crate::analysis::conflict_set(&spec, 64, 4));
+        cache.replay(&[0, 32]);
+        assert_eq!(cache.occupancy(), 6);
+
+        assert_eq!(cache.invalidate_set(2), spec.associativity());
+        assert_eq!(cache.occupancy(), 6 - spec.associativity());
+        assert!(cache.contains(0));
+        assert!(!cache.contains(64));
+        assert_eq!(cache.invalidate_set(2), 0);
+        // a flush isn't an access
+        assert_eq!(cache.make_report().misses(), 6);
+    }
+
+    #[test]
+    fn flushed_ties() {
+        let spec = CacheSpec::new(5, 7, 2);
+        let filled_way = |tie_break| {
+            let cache = CacheState::from_spec(spec.clone()).with_tie_break(tie_break);
+            cache.replay(&crate::analysis::conflict_set(&spec, 0, 4));
+            // all four ways of set 0 are now equally empty
+            cache.invalidate_set(0);
+            cache.touch_address(8 * spec.conflict_stride());
+            let ways: Vec<bool> = cache.sets[0]
+                .iter()
+                .map(|line| line.fetch_unpack().valid)
+                .collect();
+            ways.iter().position(|&valid| valid).unwrap()
+        };
+
+        assert_eq!(filled_way(TieBreak::LowestWay), 0);
+        assert_eq!(filled_way(TieBreak::HighestWay), 3);
+        let random = filled_way(TieBreak::Random);
+        assert_eq!(filled_way(TieBreak::Random), random);
+
+        // four valid blocks that all went in as least recently used are tied too, and each
+        // block filled in their place goes in the same way, so every miss is another tie
+        let replaced_ways = |tie_break, misses: u64| {
+            let cache = CacheState::from_spec(spec.clone())
+                .with_insertion(Insertion::Bip {
+                    probability: 0.0,
+                    seed: 0,
+                })
+                .with_tie_break(tie_break);
+            cache.replay(&crate::analysis::conflict_set(&spec, 0, 4));
+            assert!(cache.ways(0).iter().all(Option::is_some));
+            (0..misses)
+                .map(|i| {
+                    let before = cache.ways(0);
+                    cache.touch_address((4 + i) * spec.conflict_stride());
+                    let after = cache.ways(0);
+                    (0..4).find(|&way| before[way] != after[way]).unwrap()
+                })
+                .collect::<Vec<usize>>()
+        };
+
+        assert_eq!(replaced_ways(TieBreak::LowestWay, 4), [0; 4]);
+        assert_eq!(replaced_ways(TieBreak::HighestWay, 4), [3; 4]);
+        // every draw is seeded afresh, so some of them land past the lowest way
+        let random = replaced_ways(TieBreak::Random, 8);
+        assert!(random.iter().any(|&way| way != 0), "{random:?}");
+        assert_eq!(replaced_ways(TieBreak::Random, 8), random);
+    }
+
+    #[test]
+    fn bank_conflicts() {
+        let spec = CacheSpec::spec_8kib_32bit_2way().with_banks(4);
+        let cache = CacheState::from_spec(spec.clone());
+        assert_eq!(spec.bank_of(32 * 5), 1);
+
+        // sets 1 and 5 share a bank, sets 1 and 2 don't
+        for i in 0..8 {
+            cache.touch_pair(32 + 4096 * i, 32 * 5);
+        }
+        cache.touch_pair(32, 64);
+        // the same block twice is a single bank access
+        cache.touch_pair(32, 40);
+        assert_eq!(cache.make_report().bank_conflicts(), 8);
+    }
+
+    #[test]
+    fn set_balance() {
+        let spec = CacheSpec::spec_8kib_32bit_2way();
+        let linear = CacheState::from_spec(spec.clone());
+        linear.touch_iter(crate::patterns::sequential(0, 8, 4096));
+        assert_eq!(linear.make_report().set_balance(), 0.0);
+
+        let thrash = CacheState::from_spec(spec.clone());
+        for _ in 0..10 {
+            thrash.replay(&crate::analysis::conflict_set(&spec, 0, 3));
+        }
+        let report = thrash.make_report();
+        assert_eq!(report.misses_per_set()[0], 30);
+        // everything in one of 128 sets
+        assert!((report.set_balance() - 127f64.sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn saturation_point() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        // every block twice before moving on, so the 256th block arrives at access 511
+        cache.touch_iter(crate::patterns::sequential(0, 32, 255).flat_map(|a| [a, a]));
+        assert_eq!(cache.make_report().first_saturation_access(), None);
+        cache.touch_address(255 * 32);
+        cache.touch_iter(crate::patterns::sequential(8192, 32, 256));
+        let report = cache.make_report();
+        assert_eq!(report.occupancy(), 256);
+        assert_eq!(report.first_saturation_access(), Some(511));
+
+        // invalidating and refilling doesn't move it
+        cache.invalidate(0);
+        cache.touch_address(0);
+        assert_eq!(cache.make_report().first_saturation_access(), Some(511));
+    }
+
+    #[test]
+    fn average_utilization() {
+        // 16 blocks in a 256 line cache
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        for _ in 0..100 {
+            cache.touch_iter(crate::patterns::sequential(0, 32, 16));
+        }
+        let report = cache.make_report();
+        assert_eq!(report.occupancy(), 16);
+        let utilization = report.utilization();
+        assert!(utilization < 16.0 / 256.0 && utilization > 15.0 / 256.0);
+
+        // the 16 blocks already there hit, then every miss adds a line until the cache is full
+        cache.reset_stats();
+        cache.touch_iter(crate::patterns::sequential(0, 32, 256));
+        let integral = 16 * 16 + (17..=256).sum::<u64>();
+        let expected = integral as f64 / (256.0 * 256.0);
+        assert!((cache.make_report().utilization() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn access_count_across_epoch_wrap() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        cache.epoch.store(u64::MAX - 1, Ordering::Relaxed);
+        cache.reset_stats();
+        cache.touch_iter(crate::patterns::sequential(0, 32, 4));
+        let report = cache.make_report();
+        assert_eq!(report.access_count(), 4);
+        assert_eq!(report.misses(), 4);
+    }
+
+    #[test]
+    fn index_bits() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        // a 128 byte stride over 32 byte blocks never sets the two lowest index bits
+        cache.touch_iter(crate::patterns::sequential(0, 128, 1024));
+        let usage = cache.make_report().index_bit_usage();
+        assert_eq!(usage.len(), 7);
+        assert_eq!(usage[..2], [0.0, 0.0]);
+        assert!(usage[2..].iter().all(|&share| share == 0.5));
+    }
+
+    #[test]
+    fn direct_mapped() {
+        let spec = CacheSpec::new(5, 7, 0);
+        let cache = CacheState::from_spec(spec.clone());
+        let trace = crate::patterns::random_uniform(0, 1 << 14, 2000, 5).collect::<Vec<_>>();
+
+        // the line a block maps to holds whichever block touched it last
+        let mut lines = vec![None; spec.set_num()];
+        let mut expected_hits = 0;
+        for &address in &trace {
+            let block = address >> spec.block_size_bits();
+            let line = &mut lines[spec.index_of(address)];
+            if *line == Some(block) {
+                expected_hits += 1;
+            }
+            *line = Some(block);
+        }
+        cache.replay(&trace);
+        assert_eq!(cache.make_report().hits(), expected_hits);
+
+        // two conflicting blocks replace each other every time
+        let conflicting = CacheState::from_spec(spec.clone());
+        for _ in 0..5 {
+            conflicting.replay(&[64, 64 + spec.conflict_stride()]);
+        }
+        let report = conflicting.make_report();
+        assert_eq!(report.hits(), 0);
+        assert_eq!(report.evictions_per_set()[2], 9);
+    }
+
+    #[test]
+    fn trashing() {
+        let spec = CacheSpec::spec_8kib_32bit_2way();
+        let cache = CacheState::from_spec(spec.clone());
+        let array_size = 512;
+        let element_size = 8;
+
+        let a_base = 4200;
+        let b_base = a_base + array_size * element_size;
+        let c_base = b_base + array_size * element_size;
+
+        for i in 0..12 {
+            let a_addr = a_base + element_size * i;
+            let b_addr = b_base + element_size * i;
+            let c_addr = c_base + element_size * i;
+
+            cache.touch_address(a_addr);
+            cache.touch_address(b_addr);
+            cache.touch_address(c_addr);
+        }
+
+        let report = cache.make_report();
+        assert_eq!(report.access_count, 36);
+        assert_eq!(report.spec.size(), 8192);
+        assert_eq!(report.hits, 0);
+        assert_eq!(report.misses, 36);
+        assert_eq!(report.mpki(), 1000.0);
+
+        // the three arrays only collide in sets 3 to 6, the first two fills of each were cold
+        let evictions = report.evictions_per_set();
+        assert_eq!(evictions[3..7], [7, 10, 10, 1]);
+        assert_eq!(evictions.iter().sum::<u64>(), 36 - 8);
+    }
+
+    #[test]
+    fn thrashing_lifetimes() {
+        let mut cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        cache.enable_lifetime_tracking();
+        let stride = 512 * 8;
+
+        for i in 0..12 {
+            cache.touch_address(4200 + 8 * i);
+            cache.touch_address(4200 + stride + 8 * i);
+            cache.touch_address(4200 + 2 * stride + 8 * i);
+        }
+
+        // every line is evicted two accesses after it was brought in
+        let report = cache.make_report();
+        let ages = report.eviction_ages().unwrap();
+        assert_eq!(ages.evictions() as usize, 36 - report.occupancy());
+        assert_eq!(ages.buckets()[1], ages.evictions());
+        assert_eq!(LifetimeHistogram::bucket_range(1), (2, 3));
+    }
+
+    #[test]
+    fn indexed_access() {
+        let linear = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        let indexed = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        for i in 0..128 {
+            linear.touch_address(4200 + 8 * i as u64);
+            assert_eq!(indexed.touch_indexed(4200, i, 8).accesses(), 1);
+        }
+        assert_eq!(indexed.make_report(), linear.make_report());
+        assert_eq!(indexed.make_report().misses(), 33);
+
+        // the 12 byte elements from 24 to 36 cross into the next block
+        let outcome = indexed.touch_indexed(0, 2, 12);
+        assert_eq!(outcome.accesses(), 2);
+
+        // past the top of the address space the element wraps round to 0
+        let wrapped = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        assert_eq!(wrapped.touch_indexed(u64::MAX - 31, 2, 16).accesses(), 1);
+        assert!(wrapped.contains(0));
+    }
+
+    #[test]
+    fn linear_access() {
+        let spec = CacheSpec::spec_8kib_32bit_2way();
+        let cache = CacheState::from_spec(spec.clone());
+        let element_size = 8;
+
+        let a_base = 4200;
+
+        for i in 0..128 {
+            let a_addr = a_base + element_size * i;
+            cache.touch_address(a_addr);
+        }
+
+        let report = cache.make_report();
+        assert_eq!(report.access_count, 128);
+        assert_eq!(report.spec.size(), 8192);
+        // a_base isn't block aligned, so the 1KiB scan spans 33 blocks
+        assert_eq!(report.hits, 95);
+        assert_eq!(report.misses, 33);
+        assert_eq!(report.mpki(), 33.0 * 1000.0 / 128.0);
+        assert_eq!(report.read_traffic_bytes(), 33 * 32);
+        assert_eq!(report.writeback_traffic_bytes(), None);
+        let empty = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).make_report();
+        assert_eq!(empty.mpki(), 0.0);
+    }
+
+    #[test]
+    fn writeback_traffic() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way())
+            .with_write_policy(WritePolicy::WriteBack);
+        let stride = 4096;
+
+        // three blocks fighting over one 2 way set, only the first is dirty
+        cache.touch_address_write(0);
+        cache.touch_address(stride);
+        cache.touch_address(2 * stride);
+        cache.touch_address(0);
+        cache.touch_address(stride);
+
+        let report = cache.make_report();
+        assert_eq!(report.misses, 5);
+        assert_eq!(report.read_traffic_bytes(), 5 * 32);
+        assert_eq!(report.writebacks(), Some(1));
+        assert_eq!(report.writeback_traffic_bytes(), Some(32));
+    }
+
+    #[test]
+    fn write_through() {
+        let writes = [0, 0, 32, 4096, 8192, 0];
+        let through = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way())
+            .with_write_policy(WritePolicy::WriteThrough);
+        let back = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way())
+            .with_write_policy(WritePolicy::WriteBack);
+        for cache in [&through, &back] {
+            for &address in &writes {
+                cache.touch_address_write(address);
+            }
+            cache.touch_address(64);
+        }
+
+        let (through, back) = (through.make_report(), back.make_report());
+        // hits and misses alike, reads never
+        assert_eq!(through.memory_writes(), Some(writes.len() as u64));
+        assert_eq!(through.writebacks(), None);
+        assert_eq!(back.memory_writes(), None);
+        // while write-back only pays for 0 and 4096 being evicted dirty from their set
+        assert_eq!(back.writebacks(), Some(2));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn prometheus_metrics() {
+        let cache = CacheState::from_spec(CacheSpec::new(5, 2, 1));
+        cache.replay(&[0, 0, 0, 32]);
+
+        let text = cache.make_report().to_prometheus("l1");
+        assert!(text.contains("# TYPE l1_accesses gauge\nl1_accesses 4\n"));
+        assert!(text.contains("\nl1_hits 2\n"));
+        assert!(text.contains("\nl1_misses 2\n"));
+        assert!(text.contains("\nl1_hit_rate 0.5\n"));
+        assert!(text.contains("# HELP l1_set_misses "));
+        assert!(text.contains("\nl1_set_misses{set=\"1\"} 1\n"));
+        assert!(text.ends_with("l1_set_misses{set=\"3\"} 0\n"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn timeline_json_downsampled() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).with_timeline(10);
+
+        for i in 0..1000 {
+            cache.touch_address(4200 + 8 * i);
+        }
+
+        let report = cache.make_report();
+        assert_eq!(report.timeline().unwrap().points().len(), 100);
+
+        let json = report.timeline_json(Some(30)).unwrap();
+        let points: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let points = points.as_array().unwrap();
+        assert_eq!(points.len(), 25);
+        assert_eq!(points[1]["window"], 4);
+        assert_eq!(points[0]["accesses"], 40);
+    }
+
+    #[test]
+    fn mshr_stalls() {
+        // eight back to back misses to distinct lines
+        let trace: Vec<u64> = (0..8).map(|i| 4096 + 32 * i).collect();
+
+        let scarce =
+            CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).with_mshrs_latency(2, 16);
+        scarce.replay(&trace);
+        let stats = scarce.make_report().mshr_stats.unwrap();
+        assert_eq!(stats.max_concurrency, 2);
+        assert_eq!(stats.stalls, 6);
+
+        let plenty =
+            CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).with_mshrs_latency(8, 16);
+        plenty.replay(&trace);
+        let stats = plenty.make_report().mshr_stats.unwrap();
+        assert_eq!(stats.max_concurrency, 8);
+        assert_eq!(stats.stalls, 0);
+    }
+
+    #[test]
+    fn set_pressure() {
+        let spec = CacheSpec::with_ways(5, 7, 4);
+        let cache = CacheState::from_spec(spec.clone());
+        assert_eq!(cache.make_report().most_pressured_set(), None);
+
+        // a couple of lines spread over the cache, then one set filled up
+        cache.replay(&[0, 32, 64]);
+        cache.replay(&crate::analysis::conflict_set(&spec, 4200, 6));
+
+        let report = cache.make_report();
+        assert_eq!(report.max_set_occupancy(), 4);
+        assert_eq!(report.most_pressured_set(), Some(spec.index_of(4200)));
+    }
+
+    #[test]
+    fn snapshot_matches_report() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way());
+        cache.replay(&[0, 32, 0, 4096, 8192, 0]);
+        cache.touch_address_nontemporal(64);
+
+        let stats = cache.stats_snapshot();
+        let report = cache.make_report();
+        assert_eq!(stats.access_count, report.access_count());
+        assert_eq!(stats.hits, report.hits());
+        assert_eq!(stats.misses, report.misses());
+        assert_eq!(stats.streaming_accesses, report.streaming_accesses());
+        assert_eq!(stats.occupancy, report.occupancy());
+        assert_eq!(stats, cache.stats_snapshot());
+    }
+
+    #[test]
+    fn associativity_penalty() {
+        // three blocks in one 2 way set thrash, while 512 fully associative lines hold them easily
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).with_fa_oracle();
+        for _ in 0..10 {
+            cache.replay(&[0, 4096, 8192]);
+        }
+
+        let report = cache.make_report();
+        assert_eq!(report.misses(), 30);
+        assert_eq!(report.oracle_misses(), Some(3));
+        assert_eq!(report.oracle_hits(), Some(27));
+    }
+
+    #[test]
+    fn hit_reuse_distance() {
+        let mean = |trace: &[u64]| {
+            let cache =
+                CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).with_reuse_distances();
+            cache.replay(trace);
+            cache.make_report().mean_hit_reuse_distance().unwrap()
+        };
+
+        // a loop over two blocks only ever has the other block in between
+        let tight = [0, 32].repeat(500);
+        assert_eq!(mean(&tight), 1.0);
+
+        // 200 blocks spread over the whole cache, revisited in the same order
+        let lap: Vec<u64> = (0..200).map(|i| 40 * i).collect();
+        let scattered = lap.repeat(10);
+        assert!(mean(&scattered) > 150.0);
+    }
+
+    #[test]
+    fn event_ring() {
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way()).with_event_buffer(3);
+        cache.replay(&[0, 4096, 4, 8192]);
+
+        let report = cache.make_report();
+        assert_eq!(
+            report.recent_events().unwrap(),
+            [
+                CacheEvent::Hit { address: 4 },
+                CacheEvent::Miss { address: 8192 },
+                CacheEvent::Eviction { block: 4096 },
+            ]
+        );
+        assert_eq!(report.dropped_events(), Some(2));
+        assert_eq!(
+            CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way())
+                .make_report()
+                .recent_events(),
+            None
+        );
+    }
+
+    #[test]
+    fn report_into_buffer() {
+        let mut cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way())
+            .with_write_policy(WritePolicy::WriteBack);
+        cache.set_address_filter(Box::new(|address| address < 1 << 20));
+        cache.touch_address(1 << 20);
+        cache.touch_address_write(0);
+        cache.replay(&[4096, 8192, 0, 32]);
+        cache.touch_pair(64, 64 + 4096);
+        cache.touch_cycle(&[CacheAccess::read(0), CacheAccess::read(32)]);
+
+        let mut buf = ReportBuf::default();
+        cache.write_report_into(&mut buf);
+        let report = cache.make_report();
+        assert_eq!(buf.access_count, report.access_count());
+        assert_eq!(buf.hits, report.hits());
+        assert_eq!(buf.misses, report.misses());
+        assert_eq!(buf.streaming_accesses, report.streaming_accesses());
+        assert_eq!(buf.bank_conflicts, report.bank_conflicts());
+        assert_eq!(buf.port_conflicts, report.port_conflicts());
+        assert_eq!(buf.port_conflicts, 1);
+        assert_eq!(buf.filtered_accesses, report.filtered_accesses());
+        assert_eq!(buf.filtered_accesses, 1);
+        assert_eq!(buf.occupancy, report.occupancy());
+        assert_eq!(buf.max_set_occupancy, report.max_set_occupancy());
+        assert_eq!(buf.most_pressured_set, report.most_pressured_set());
+        assert_eq!(buf.writebacks, report.writebacks());
+        assert_eq!(buf.writebacks, Some(1));
+        assert_eq!(buf.memory_writes, None);
+
+        let cache = CacheState::from_spec(CacheSpec::spec_8kib_32bit_2way())
+            .with_write_policy(WritePolicy::WriteThrough);
+        cache.touch_address_write(0);
+        cache.touch_address_write(32);
+        cache.write_report_into(&mut buf);
+        let report = cache.make_report();
+        assert_eq!(buf.memory_writes, report.memory_writes());
+        assert_eq!(buf.memory_writes, Some(2));
+        assert_eq!(buf.writebacks, None);
+    }
+
+    #[test]
+    fn dump_contents() {
+        let cache = CacheState::from_spec(CacheSpec::new(5, 2, 1));
+        cache.replay(&[0, 128, 32]);
+        let dump = cache.dump();
+        let rows: Vec<&str> = dump.lines().collect();
+        assert_eq!(rows.len(), 4);
+        assert!(rows[0].starts_with("set     0 |"));
+        assert!(rows[0].contains("0x00000000@0"));
+        assert!(rows[0].contains("0x00000001@1"));
+        assert!(rows[1].contains("0x00000000@2"));
+        assert_eq!(rows[2].matches('-').count(), 2);
+
+        // dumping doesn't count as a use, so the older line is still the one evicted
+        cache.dump();
+        cache.touch_address(256);
+        assert!(!cache.contains(0));
+        assert!(cache.contains(128));
+    }
+
+    #[test]
+    fn zero_tag() {
+        let spec = CacheSpec::spec_8kib_32bit_2way();
+        let cache = CacheState::from_spec(spec.clone());
+        assert_eq!(spec.split(64).1, 0);
+
+        // an empty line mustn't pass for a cached block with tag 0
+        assert!(!cache.contains(64));
+        assert!(!cache.touch_address(64));
+        assert!(cache.touch_address(64));
+        assert!(!cache.touch_address(0));
+        assert!(cache.touch_address(0));
+        assert_eq!(cache.occupancy(), 2);
+    }
+
+    #[test]
+    fn pack_unpack() {
+        let line = CacheLineCompact::new();
+        let val = CacheLine::filled(0xABCDEFAB, 0x0123456);
+        line.pack_store(val.clone());
+        let returned = line.fetch_unpack();
+
+        assert_eq!(val, returned)
+    }
+
+    #[test]
+    fn state_bits() {
+        let line = CacheLineCompact::new();
+        for (dirty, lru) in [(false, true), (true, false), (true, true)] {
+            let val = CacheLine {
+                tag: u32::MAX,
+                access: ACCESS_MASK,
+                valid: true,
+                dirty,
+                lru,
+            };
+            line.pack_store(val.clone());
+            assert_eq!(line.fetch_unpack(), val);
+        }
+
+        // a hit moves the access on, wrapping it into its bits, and takes the line out of the
+        // least recently used position but leaves it dirty
+        assert_eq!(line.touch_if_matches(u32::MAX, ACCESS_MASK + 5), Ok(()));
+        let touched = line.fetch_unpack();
+        assert_eq!(touched.access, 4);
+        assert!(touched.dirty && !touched.lru && touched.valid);
+        assert_eq!(touched.tag, u32::MAX);
+
+        // an access wider than the field can't spill into the state bits or the tag
+        line.pack_store(CacheLine::filled(7, u32::MAX));
+        assert_eq!(line.fetch_unpack(), CacheLine::filled(7, ACCESS_MASK));
     }
 }
@@ -0,0 +1,122 @@
+use std::sync::Mutex;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Hit and miss counts bucketed into fixed size windows of accesses
+pub(crate) struct TimelineRecorder {
+    window_size: u32,
+    windows: Mutex<Vec<(u32, u32)>>,
+}
+
+impl TimelineRecorder {
+    pub fn new(window_size: u32) -> TimelineRecorder {
+        assert!(window_size > 0, "timeline window size must be non-zero");
+        TimelineRecorder {
+            window_size,
+            windows: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn record(&self, epoch: u32, hit: bool) {
+        let window = (epoch / self.window_size) as usize;
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() <= window {
+            windows.resize(window + 1, (0, 0));
+        }
+        let (hits, misses) = &mut windows[window];
+        if hit {
+            *hits += 1;
+        } else {
+            *misses += 1;
+        }
+    }
+
+    pub fn snapshot(&self) -> Timeline {
+        let windows = self.windows.lock().unwrap();
+        let points = windows
+            .iter()
+            .enumerate()
+            .map(|(window, &(hits, misses))| TimelinePoint::new(window, hits, misses))
+            .collect();
+        Timeline {
+            window_size: self.window_size,
+            points,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct TimelinePoint {
+    /// index of the first window (in units of the original window size) covered by this point
+    pub window: usize,
+    pub accesses: u32,
+    pub hits: u32,
+    pub misses: u32,
+    pub hit_rate: f64,
+}
+
+impl TimelinePoint {
+    fn new(window: usize, hits: u32, misses: u32) -> TimelinePoint {
+        let accesses = hits + misses;
+        let hit_rate = if accesses == 0 {
+            0.0
+        } else {
+            hits as f64 / accesses as f64
+        };
+        TimelinePoint {
+            window,
+            accesses,
+            hits,
+            misses,
+            hit_rate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeline {
+    window_size: u32,
+    points: Vec<TimelinePoint>,
+}
+
+impl Timeline {
+    /// number of accesses covered by each point
+    pub fn window_size(&self) -> u32 {
+        self.window_size
+    }
+
+    pub fn points(&self) -> &[TimelinePoint] {
+        &self.points
+    }
+
+    /// merges adjacent windows so that at most `max_points` remain
+    pub fn downsample(&self, max_points: usize) -> Timeline {
+        let max_points = max_points.max(1);
+        if self.points.len() <= max_points {
+            return self.clone();
+        }
+
+        let group = self.points.len().div_ceil(max_points);
+        let points = self
+            .points
+            .chunks(group)
+            .map(|chunk| {
+                let hits = chunk.iter().map(|p| p.hits).sum();
+                let misses = chunk.iter().map(|p| p.misses).sum();
+                TimelinePoint::new(chunk[0].window, hits, misses)
+            })
+            .collect();
+        Timeline {
+            window_size: self.window_size * group as u32,
+            points,
+        }
+    }
+
+    /// array of `{ window, accesses, hits, misses, hit_rate }` objects
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.points).expect("timeline points are always serializable")
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// writes allocate and mark the line dirty, dirty lines are written to memory when evicted
+    WriteBack,
+    /// every write goes straight to memory as well, so lines are never dirty
+    WriteThrough,
+}
+
+/// Dirty blocks written to memory under [`WritePolicy::WriteBack`], the dirty bits themselves are
+/// kept in the lines
+#[derive(Default)]
+pub(crate) struct Writebacks(AtomicU64);
+
+impl Writebacks {
+    /// a block was replaced, `dirty` is whether it had been written to since it was filled
+    pub fn evicted(&self, dirty: bool) {
+        if dirty {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// dirty blocks evicted so far
+    pub fn count(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,35 @@
+//! `#[derive(Thrashe)]` expands to paths through `::thrashe`, so it's tested from outside the crate
+#![cfg(feature = "derive")]
+
+use thrashe::{provider::CacheProvider, thrashe::CacheSpec};
+
+thrashe::new_provider!(DeriveCache);
+
+#[derive(thrashe::Thrashe)]
+struct Particle {
+    position: [f64; 3],
+    mass: f64,
+    #[thrashe(skip)]
+    name: String,
+}
+
+#[test]
+fn instrumented_fields() {
+    DeriveCache::configure(CacheSpec::spec_8kib_32bit_2way());
+    let particle = Particle {
+        position: [0.0; 3],
+        mass: 2.0,
+        name: "electron".to_owned(),
+    }
+    .instrument_in::<DeriveCache>();
+
+    for _ in 0..5 {
+        let _ = particle.position()[0] * particle.mass();
+    }
+    // skipped fields are plain fields, reading them counts for nothing
+    assert_eq!(particle.name, "electron");
+
+    let report = DeriveCache::finish().unwrap();
+    assert_eq!(report.access_count(), 10);
+    assert!(report.misses() <= 2);
+}
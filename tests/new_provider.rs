@@ -0,0 +1,19 @@
+//! `new_provider!` used from another crate, where only the paths it spells out through `$crate`
+//! resolve
+
+use thrashe::{provider::CacheProvider, thrashe::CacheSpec};
+
+thrashe::new_provider!(ExternalCache);
+
+#[cfg(feature = "convenience_types")]
+#[allow(dead_code)]
+fn convenience_alias(value: ThrasheExternalCache<u8>) -> thrashe::Thrashe<u8, ExternalCache> {
+    value
+}
+
+#[test]
+fn external_provider() {
+    assert!(ExternalCache::configure(CacheSpec::spec_8kib_32bit_2way()).is_none());
+    assert!(ExternalCache::finish().is_some());
+    assert!(ExternalCache::finish().is_none());
+}
@@ -0,0 +1,109 @@
+//! `#[derive(Thrashe)]`, re-exported by `thrashe` behind its `derive` feature
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Data, DeriveInput, Fields, parse_macro_input, spanned::Spanned};
+
+/// Generates `<Name>Thrashe<C = GlobalCache>`, the struct with every field wrapped in a `Thrashe`
+/// recording into `C`, plus `Name::instrument` and `Name::instrument_in::<C>` to build it and an
+/// accessor per field that counts as an access
+///
+/// fields marked `#[thrashe(skip)]` are kept as they are and never counted
+#[proc_macro_derive(Thrashe, attributes(thrashe))]
+pub fn derive_thrashe(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "Thrashe can only be derived for structs with named fields",
+                ));
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "Thrashe can only be derived for structs",
+            ));
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "Thrashe can't be derived for generic structs",
+        ));
+    }
+
+    let vis = &input.vis;
+    let name = &input.ident;
+    let wrapper = format_ident!("{name}Thrashe");
+
+    let mut wrapped_fields = Vec::new();
+    let mut builds = Vec::new();
+    let mut accessors = Vec::new();
+    for field in fields {
+        let skip = field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("thrashe"))
+            .map(|attr| {
+                let mut skip = false;
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("skip") {
+                        skip = true;
+                        Ok(())
+                    } else {
+                        Err(meta.error("unknown thrashe attribute, expected `skip`"))
+                    }
+                })?;
+                Ok(skip)
+            })
+            .collect::<syn::Result<Vec<bool>>>()?
+            .contains(&true);
+
+        let (field_vis, ident, ty) = (&field.vis, field.ident.as_ref().unwrap(), &field.ty);
+        if skip {
+            wrapped_fields.push(quote! { #field_vis #ident: #ty });
+            builds.push(quote! { #ident: self.#ident });
+        } else {
+            wrapped_fields.push(quote! { #field_vis #ident: ::thrashe::Thrashe<#ty, C> });
+            builds.push(quote! { #ident: ::thrashe::Thrashe::new_in(self.#ident) });
+            accessors.push(quote! {
+                #field_vis fn #ident(&self) -> &#ty {
+                    &self.#ident
+                }
+            });
+        }
+    }
+
+    Ok(quote! {
+        #vis struct #wrapper<C: ::thrashe::provider::CacheProvider = ::thrashe::provider::GlobalCache> {
+            #(#wrapped_fields,)*
+        }
+
+        impl #name {
+            /// wraps every field so accesses are recorded into [`::thrashe::provider::GlobalCache`]
+            #vis fn instrument(self) -> #wrapper {
+                self.instrument_in()
+            }
+
+            #vis fn instrument_in<C: ::thrashe::provider::CacheProvider>(self) -> #wrapper<C> {
+                #wrapper {
+                    #(#builds,)*
+                }
+            }
+        }
+
+        impl<C: ::thrashe::provider::CacheProvider> #wrapper<C> {
+            #(#accessors)*
+        }
+    })
+}